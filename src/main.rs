@@ -1,71 +1,44 @@
 mod search;
+#[cfg(test)]
 mod tests;
-use chess::{self, BoardStatus, ChessMove};
-use chess::{Board, Color};
-use log::debug;
-use search::transposition_table::{Flag, TransTable, TransTableEntry, self};
-use std::io;
-use std::str::FromStr;
-use std::sync::{Mutex, Arc};
+mod uci;
+mod xboard;
+
+use std::io::{self, BufRead};
+
+/// Name both front-ends report to the GUI
+pub const ENGINE_NAME: &str = "rootygine";
 
 fn main() {
     env_logger::init();
-    player_play();
-}
-
-fn player_play() {
-    let mut board = Board::default();
-    let mut tt = Arc::new(Mutex::new(transposition_table::TransTable::new()));
-    loop {
-        let mut buffer = String::new();
-        let stdin = io::stdin(); // We get `Stdin` here.
-        stdin.read_line(&mut buffer).unwrap();
-        let player_move = ChessMove::from_str(buffer.trim()).unwrap();
-        if board.legal(player_move) {
-            board = board.make_move_new(player_move);
-        } else {
-            debug!("Enter an FEN of the position AFTER you moved: ");
-            let mut buffer = String::new();
-            stdin.read_line(&mut buffer).unwrap();
-            match Board::from_str(buffer.trim()) {
-                Ok(board_new) => {
-                    board = board_new;
-                    debug!("Successfully made new board");
-                }
-                Err(_) => {
-                    debug!("{}", buffer);
 
-                    debug!("FAILED at parsing FEN");
-                    board = Board::default();
-                }
-            }
-        }
+    // GUIs announce which protocol they speak with their first command
+    let mut first_command = String::new();
+    if io::stdin().read_line(&mut first_command).is_err() {
+        return;
+    }
 
-        if board.status() == BoardStatus::Checkmate || board.status() == BoardStatus::Stalemate {
-            break;
+    if first_command.trim() == "xboard" {
+        xboard::XBoard::new().run();
+    } else {
+        let mut uci = uci::Uci::new();
+        if uci.handle_command(&first_command) {
+            uci.run();
         }
+    }
+}
 
-        let color_to_move = Color::Black;
-
-        let engine_move =
-            search::iterative_deepening_search(board, color_to_move, 7,  Some(tt.clone()));
-        board = board.make_move_new(engine_move);
-        println!("Engine move: {}", engine_move);
+/// Hands every line of stdin to `handle_command`, until stdin is closed or it returns false.
+pub fn read_commands(mut handle_command: impl FnMut(&str) -> bool) {
+    let stdin = io::stdin();
+    for line in stdin.lock().lines() {
+        let line = match line {
+            Ok(line) => line,
+            Err(_) => break,
+        };
 
-        if board.status() == BoardStatus::Checkmate || board.status() == BoardStatus::Stalemate {
+        if !handle_command(&line) {
             break;
         }
     }
 }
-
-fn testing() {
-    let color_to_move = Color::Black;
-    let board =
-        Board::from_str("rnbqkb1r/pppp1ppp/5n2/4P3/5p2/2N5/PPPP2PP/R1BQKBNR b KQkq - 0 4")
-            .expect("Invalid FEN");
-    let best_move =
-        search::iterative_deepening_search(board, color_to_move, 7, None);
-    debug!("Test");
-
-    println!("Top Engine Move: {}", best_move);
-}
@@ -1,27 +1,373 @@
-use std::cmp::{Ordering, max};
+use std::cmp::Ordering;
+use std::fmt;
+use std::str::FromStr;
 use std::thread;
 
 use chess::BoardStatus;
-use chess::{Board, ChessMove, Color, MoveGen};
+use chess::{Board, ChessMove, Color, MoveGen, Piece, Rank};
 use log::debug;
-use rand::{prelude::SmallRng, SeedableRng};
 use std::collections::VecDeque;
+use std::sync::atomic::{AtomicBool, Ordering as AtomicOrdering};
 use std::sync::Arc;
 use std::sync::Mutex;
+use std::time::{Duration, Instant};
 
+use move_ordering::{MovePicker, OrderingTables};
+use pv::PvTable;
+use time_manager::TimeManager;
 use transposition_table::{Flag, TransTable, TransTableEntry};
 
-use self::utils::flip_color;
-
 mod evaluate;
+pub mod move_ordering;
+mod pv;
+pub mod see;
 mod threading;
+mod time_manager;
 pub mod transposition_table;
 mod utils;
 
+// Deepest iteration a search will ever start when it isn't given a depth
+const MAX_DEPTH: i32 = 64;
+
+// Same depth player_play used to search at, used when nothing else would ever end the search
+const DEFAULT_DEPTH: i32 = 7;
+
+// No line is ever followed further than this, no matter what extends it
+const MAX_PLY: usize = 128;
+
+// Width of the windows PVS and MTD(f) use to only find out which side of a value the eval is on
+const NULL_WINDOW: f32 = 0.01;
+
+// MTD(f) has to converge within this many null window searches, floats don't always land
+// exactly on the eval
+const MTDF_MAX_PASSES: usize = 32;
+
+// Null moves are only tried this many plies away from the horizon. Past that the reduction
+// leaves nothing to search.
+const NULL_MOVE_MIN_DEPTH: i32 = 3;
+
+// With this many pieces besides pawns and the king or less, zugzwang is likely enough that null
+// move cutoffs get verified
+const NULL_MOVE_VERIFICATION_PIECES: u32 = 2;
+
+// Late move reductions only kick in this far from the horizon, and only after this many moves
+// have been searched at full depth
+const LMR_MIN_DEPTH: i32 = 3;
+const LMR_MIN_MOVES: usize = 3;
+// Size of both dimensions of the reduction table, anything further out uses the last entry
+const LMR_TABLE_SIZE: usize = 64;
+
+// Late move pruning skips the remaining quiet moves this close to the horizon, once
+// 3 + depth * depth of them have been searched
+const LMP_MAX_DEPTH: i32 = 3;
+
+// Iterations from this depth on start out searching this far (in pawns) on either side of the
+// last iteration's eval. Whenever the eval lands outside, the window gets twice as wide on that
+// side, and once it's wider than the max it's left open.
+const ASPIRATION_MIN_DEPTH: i32 = 4;
+const ASPIRATION_WINDOW: f32 = 0.25;
+const ASPIRATION_MAX_WINDOW: f32 = 5.0;
+
+// How far (in pawns per ply of depth left) the static eval has to be above beta for reverse
+// futility pruning to trust it, and below alpha for futility pruning and razoring to give up on
+// the position. All of them only apply this close to the horizon.
+const REVERSE_FUTILITY_MARGIN: f32 = 1.2;
+const REVERSE_FUTILITY_MAX_DEPTH: i32 = 3;
+const FUTILITY_MARGIN: f32 = 1.0;
+const FUTILITY_MAX_DEPTH: i32 = 2;
+const RAZORING_MARGIN: f32 = 3.0;
+const RAZORING_MAX_DEPTH: i32 = 2;
+
+// Singular extensions are only tried this far from the horizon, with a transposition table entry
+// at most this much shallower than the node. The hash move is singular if every other move
+// stays below the entry's eval minus the margin (in pawns) in a search of half the depth.
+const SINGULAR_MIN_DEPTH: i32 = 6;
+const SINGULAR_MAX_TT_DEPTH_GAP: i32 = 3;
+const SINGULAR_MARGIN: f32 = 0.5;
+
+// Checking the clock is comparatively slow, so only do it every so many nodes
+const NODES_BETWEEN_TIME_CHECKS: u64 = 1024;
+
+/// Eval of being checkmated right now. A mate n plies away is worth CHECKMATE - n, so shorter
+/// mates are always preferred.
+pub const CHECKMATE: f32 = 10000.0;
+// Anything further from 0 than this is a forced mate and not a material count
+const MATE_THRESHOLD: f32 = 9000.0;
+
+// Positional swing (in pawns) a capture could still bring on top of the material it wins
+const DELTA_MARGIN: f32 = 2.0;
+
+/// What a search is allowed to spend. Anything left as None doesn't limit the search. Without
+/// any limit, the clock of the side not to move included, it stops at DEFAULT_DEPTH.
+/// Infinite searches go on until MAX_DEPTH or until they're told to stop.
+#[derive(Clone, Debug, Default)]
+pub struct SearchLimits {
+    /// Only stop when told to. The search itself still gives up at MAX_DEPTH, it's up to the
+    /// caller to hold on to the result until the stop arrives.
+    pub infinite: bool,
+    /// Searching on the opponent's time, see SearchControl::ponderhit
+    pub ponder: bool,
+    pub depth: Option<i32>,
+    /// Stop once this many nodes have been searched in total
+    pub nodes: Option<u64>,
+    /// Stop as soon as a mate in this many moves (or less) is found
+    pub mate: Option<u32>,
+    pub movetime: Option<Duration>,
+    pub wtime: Option<Duration>,
+    pub btime: Option<Duration>,
+    pub winc: Duration,
+    pub binc: Duration,
+    pub movestogo: Option<u32>,
+    /// Only these root moves are searched, empty means all of them
+    pub searchmoves: Vec<ChessMove>,
+}
+
+/// An eval the way GUIs want to see it, from the point of view of the side to move
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Score {
+    Centipawns(i32),
+    /// In moves, not plies. Negative when we are the one getting mated.
+    Mate(i32),
+}
+
+impl Score {
+    pub fn from_eval(eval: f32) -> Self {
+        if eval.abs() > MATE_THRESHOLD {
+            let plies = (CHECKMATE - eval.abs()).round() as i32;
+            let moves = (plies + 1) / 2;
+            if eval > 0.0 {
+                Score::Mate(moves)
+            } else {
+                Score::Mate(-moves)
+            }
+        } else {
+            // evaluate works in pawns
+            Score::Centipawns((eval * 100.0).round() as i32)
+        }
+    }
+}
+
+/// Shared between a running search and whoever started it, so it can be steered from outside.
 #[derive(Debug)]
-struct MoveEval {
-    chess_move: ChessMove,
-    eval: f32,
+pub struct SearchControl {
+    stop: AtomicBool,
+    /// None while pondering, the time limits only start counting from here
+    clock_start: Mutex<Option<Instant>>,
+}
+
+impl SearchControl {
+    pub fn new(pondering: bool) -> Self {
+        SearchControl {
+            stop: AtomicBool::new(false),
+            clock_start: Mutex::new(if pondering { None } else { Some(Instant::now()) }),
+        }
+    }
+
+    /// Makes the search return as soon as possible.
+    pub fn stop(&self) {
+        self.stop.store(true, AtomicOrdering::Relaxed);
+    }
+
+    pub fn is_stopped(&self) -> bool {
+        self.stop.load(AtomicOrdering::Relaxed)
+    }
+
+    /// The opponent played the move we were pondering on, so from now on it's our clock that's
+    /// running and the time limits apply.
+    pub fn ponderhit(&self) {
+        let mut clock_start = self.clock_start.lock().unwrap();
+        if clock_start.is_none() {
+            *clock_start = Some(Instant::now());
+        }
+    }
+
+    pub fn is_pondering(&self) -> bool {
+        self.clock_start.lock().unwrap().is_none()
+    }
+
+    fn clock_start(&self) -> Option<Instant> {
+        *self.clock_start.lock().unwrap()
+    }
+}
+
+/// Why a search couldn't even start
+#[derive(Clone, Debug, PartialEq)]
+pub enum SearchError {
+    /// The game is already over
+    NoLegalMoves,
+    /// searchmoves was given but none of the moves in it can be played
+    NoLegalSearchMoves,
+}
+
+impl fmt::Display for SearchError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SearchError::NoLegalMoves => write!(f, "no legal moves in this position"),
+            SearchError::NoLegalSearchMoves => write!(f, "none of the searchmoves are legal"),
+        }
+    }
+}
+
+/// Engine settings that carry over from one search to the next
+#[derive(Clone, Debug)]
+pub struct SearchOptions {
+    /// How many of the best root moves get an exact eval and a line of their own
+    pub multipv: usize,
+    pub algorithm: Algorithm,
+    /// Late move reductions and late move pruning, turning them off makes the search a lot
+    /// slower but also easier to debug
+    pub late_move_reductions: bool,
+    /// Futility pruning, reverse futility pruning and razoring, same as above
+    pub futility_pruning: bool,
+    /// Check, singular, recapture and passed pawn extensions
+    pub extensions: bool,
+}
+
+impl Default for SearchOptions {
+    fn default() -> Self {
+        SearchOptions {
+            multipv: 1,
+            algorithm: Algorithm::default(),
+            late_move_reductions: true,
+            futility_pruning: true,
+            extensions: true,
+        }
+    }
+}
+
+/// Progress report sent after every finished iteration, once for every multipv line
+#[derive(Clone, Debug)]
+pub struct SearchInfo {
+    pub depth: i32,
+    /// Deepest ply any line reached
+    pub seldepth: i32,
+    /// Which line this is, 1 is the best
+    pub multipv: usize,
+    pub score: Score,
+    /// Exact, unless the aspiration window failed and the search is about to be repeated
+    pub bound: Flag,
+    /// Counted over the whole search, not just this iteration
+    pub nodes: u64,
+    /// How full the transposition table is, in permille
+    pub hashfull: u32,
+    pub time: Duration,
+    pub pv: Vec<ChessMove>,
+}
+
+impl SearchInfo {
+    pub fn nps(&self) -> u64 {
+        let millis = self.time.as_millis().max(1) as u64;
+        self.nodes * 1000 / millis
+    }
+}
+
+/// What a finished search settled on
+#[derive(Clone, Debug)]
+pub struct SearchResult {
+    pub best_move: ChessMove,
+    pub eval: f32,
+    /// Last iteration that was finished
+    pub depth: i32,
+    /// Starts with best_move
+    pub pv: Vec<ChessMove>,
+    /// The best multipv root moves from best to worst, the first one is the same as best_move
+    pub lines: Vec<MoveEval>,
+    pub nodes: u64,
+}
+
+/// Everything a single search thread carries down through negamax
+struct SearchThread {
+    tt: Arc<TransTable>,
+    time_manager: TimeManager,
+    control: Arc<SearchControl>,
+    algorithm: Algorithm,
+    late_move_reductions: bool,
+    futility_pruning: bool,
+    extensions: bool,
+    /// A single line never gets extended by more plies than this in total, half the depth of
+    /// the iteration, or checks back and forth would never reach the horizon
+    max_extensions: i32,
+    /// How many plies the line being searched has been extended by so far
+    extended: i32,
+    /// How many plies late moves get reduced by, indexed by depth and move number
+    reductions: Box<[[i32; LMR_TABLE_SIZE]; LMR_TABLE_SIZE]>,
+    pv: PvTable,
+    ordering: Box<OrderingTables>,
+    /// The moves that led from the root to the node being searched, by ply. None for null moves.
+    moves_played: [Option<ChessMove>; MAX_PLY],
+    /// Whether the move at that ply took something, for spotting recaptures
+    captures_played: [bool; MAX_PLY],
+    /// No null moves are tried before this ply, so verification searches can't be cut short by
+    /// the same null move they are verifying
+    null_move_min_ply: i32,
+    /// How many nodes this thread may still search
+    node_limit: Option<u64>,
+    nodes: u64,
+    seldepth: i32,
+    stopped: bool,
+}
+
+impl SearchThread {
+    fn new(
+        tt: Arc<TransTable>,
+        time_manager: TimeManager,
+        control: Arc<SearchControl>,
+        options: &SearchOptions,
+        ordering: Box<OrderingTables>,
+        max_depth: i32,
+        node_limit: Option<u64>,
+    ) -> Self {
+        // Reductions grow slowly with both the depth left and how late the move comes
+        let mut reductions = Box::new([[0; LMR_TABLE_SIZE]; LMR_TABLE_SIZE]);
+        for (depth, row) in reductions.iter_mut().enumerate().skip(1) {
+            for (move_number, reduction) in row.iter_mut().enumerate().skip(1) {
+                *reduction =
+                    (0.75 + (depth as f32).ln() * (move_number as f32).ln() / 2.25) as i32;
+            }
+        }
+
+        SearchThread {
+            tt,
+            time_manager,
+            control,
+            algorithm: options.algorithm,
+            late_move_reductions: options.late_move_reductions,
+            futility_pruning: options.futility_pruning,
+            extensions: options.extensions,
+            max_extensions: max_depth / 2,
+            extended: 0,
+            reductions,
+            pv: PvTable::new(),
+            ordering,
+            moves_played: [None; MAX_PLY],
+            captures_played: [false; MAX_PLY],
+            null_move_min_ply: 0,
+            node_limit,
+            nodes: 0,
+            seldepth: 0,
+            stopped: false,
+        }
+    }
+
+    /// Once this returns true every value coming out of negamax is garbage and gets thrown away.
+    fn should_stop(&mut self) -> bool {
+        if !self.stopped {
+            self.stopped = self.control.is_stopped()
+                || self.node_limit.is_some_and(|limit| self.nodes >= limit)
+                || (self.nodes.is_multiple_of(NODES_BETWEEN_TIME_CHECKS)
+                    && self.time_manager.hard_limit_reached());
+        }
+
+        self.stopped
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct MoveEval {
+    pub chess_move: ChessMove,
+    pub eval: f32,
+    /// Line the eval is based on, starting with chess_move
+    pub pv: Vec<ChessMove>,
 }
 
 impl PartialOrd for MoveEval {
@@ -50,141 +396,366 @@ impl Ord for MoveEval {
     }
 }
 
+/// Searches to exactly `target_depth` with the given algorithm, so they can be compared on the
+/// same positions. None if there is nothing to search.
+#[allow(dead_code)]
 pub fn new_search(
     board: Board,
     color_to_move: Color,
     target_depth: i32,
-    tt_raw: Option<Arc<Mutex<TransTable>>>,
+    algorithm: Algorithm,
+    tt_raw: Option<Arc<TransTable>>,
 ) -> Option<ChessMove> {
-
-    None
+    let limits = SearchLimits {
+        depth: Some(target_depth),
+        ..Default::default()
+    };
+    let options = SearchOptions {
+        algorithm,
+        ..Default::default()
+    };
+
+    search(
+        board,
+        color_to_move,
+        &limits,
+        &options,
+        tt_raw,
+        Arc::new(SearchControl::new(false)),
+        &mut |_| {},
+    )
+    .ok()
+    .map(|result| result.best_move)
 }
 
-enum Algorithm {
+/// How the tree below every root move gets searched. They all come up with the same eval, the
+/// ones besides MinMax just need a lot fewer nodes to get there.
+#[allow(clippy::upper_case_acronyms)]
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum Algorithm {
+    /// Looks at every single move, only there to check the others against
     MinMax,
+    /// Negamax with alpha-beta pruning
+    #[default]
     ABPrune,
+    /// Principal variation search, every move after the first only has to be shown to be worse
+    /// with a null window, unless it isn't
     PVS,
+    /// MTD(f), nothing but null window searches closing in on the eval from a first guess
     MTDF,
 }
 
-fn search_root(board: Board, depth: i32, color_to_move: Color, tt: Arc<Mutex<TransTable>>) -> Option<ChessMove> {
-
-    None
+impl Algorithm {
+    pub const ALL: [Algorithm; 4] = [
+        Algorithm::MinMax,
+        Algorithm::ABPrune,
+        Algorithm::PVS,
+        Algorithm::MTDF,
+    ];
 }
 
-fn minmax_memory(board: Board, depth: i32, color_to_move: Color, tt: Arc<Mutex<TransTable>>) -> (f32, Option<ChessMove>) {
-    // Handle Checkmate and Stalemate
-    match board.status() {
-        BoardStatus::Checkmate => return (9999.9, None),
-        BoardStatus::Stalemate => return (0.0, None),
-        BoardStatus::Ongoing => (),
-    }
-    let mut eval;
-    let mut best_move = None;
-    // Check for cached in transposition table
-    let tt_mutex = tt.lock().unwrap();
-    // TODO later
-    if depth == 0 {
-        eval =  evaluate::evaluate(board);
-    }
-    else {
-        let moves = MoveGen::new_legal(&board);
-        eval = -99999.9;
-        for possible_move in moves {
-            let score = -minmax_memory(board.make_move_new(possible_move), depth-1, flip_color(color_to_move), tt.clone()).0;
-            if eval < score {
-                eval = score;
-                best_move = Some(possible_move);
-            }
+impl fmt::Display for Algorithm {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Algorithm::MinMax => write!(f, "MinMax"),
+            Algorithm::ABPrune => write!(f, "AlphaBeta"),
+            Algorithm::PVS => write!(f, "PVS"),
+            Algorithm::MTDF => write!(f, "MTDF"),
         }
     }
-    return (eval, best_move);
 }
 
+/// Parses the names Display gives them, ignoring case.
+impl FromStr for Algorithm {
+    type Err = String;
 
-
-
-
-
-
-
-
-
-
-
-
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        Algorithm::ALL
+            .into_iter()
+            .find(|algorithm| algorithm.to_string().eq_ignore_ascii_case(name))
+            .ok_or_else(|| format!("unknown search algorithm: {}", name))
+    }
+}
 
 // Uses iterative deepening technique and transposition tables to optimize faster search
+#[allow(dead_code)]
 pub fn iterative_deepening_search(
     board: Board,
     color_to_move: Color,
     target_depth: i32,
-    tt_raw: Option<Arc<Mutex<TransTable>>>,
+    tt_raw: Option<Arc<TransTable>>,
 ) -> ChessMove {
-    // start with depth 4
-    let mut depth = 4;
-    let mut tt: Arc<Mutex<TransTable>> =
-        Arc::new(Mutex::new(transposition_table::TransTable::new()));
+    let limits = SearchLimits {
+        depth: Some(target_depth),
+        ..Default::default()
+    };
+
+    search(
+        board,
+        color_to_move,
+        &limits,
+        &SearchOptions::default(),
+        tt_raw,
+        Arc::new(SearchControl::new(false)),
+        &mut |_| {},
+    )
+    .expect("This is imepossible. There should be at least one possible move.")
+    .best_move
+}
 
-    if let Some(external_table) = tt_raw {
-        tt = external_table;
+/// Iterative deepening under the given limits, `control` lets the caller stop it or tell it
+/// about a ponderhit. When it's stopped or the time runs out mid-iteration, the moves that iteration
+/// did finish are still used if they include last iteration's best move. `on_info` is called
+/// after every finished iteration. Fails if there isn't a single root move to search.
+pub fn search(
+    board: Board,
+    color_to_move: Color,
+    limits: &SearchLimits,
+    options: &SearchOptions,
+    tt_raw: Option<Arc<TransTable>>,
+    control: Arc<SearchControl>,
+    on_info: &mut dyn FnMut(&SearchInfo),
+) -> Result<SearchResult, SearchError> {
+    let time_manager = TimeManager::new(limits, color_to_move, control.clone());
+    let unlimited = !limits.infinite
+        && limits.nodes.is_none()
+        && limits.mate.is_none()
+        && !time_manager.is_limited();
+    let mut target_depth = match limits.depth {
+        Some(depth) => depth,
+        None if unlimited => DEFAULT_DEPTH,
+        None => MAX_DEPTH,
+    }
+    .clamp(1, MAX_DEPTH);
+    // A mate in n moves is 2n - 1 plies deep, searching deeper can't find it
+    if let Some(mate) = limits.mate {
+        target_depth = target_depth.min((2 * mate as i64 - 1).clamp(1, MAX_DEPTH as i64) as i32);
+    }
+    let tt = tt_raw.unwrap_or_else(|| Arc::new(TransTable::new()));
+    // What earlier searches left behind is still worth probing, but not worth keeping around
+    tt.increment_age();
+    let multipv = options.multipv.max(1);
+    // Every search thread keeps its move ordering tables from one iteration to the next
+    let mut ordering: Vec<Box<OrderingTables>> = vec![];
+
+    let legal_moves = MoveGen::new_legal(&board);
+    if legal_moves.len() == 0 {
+        return Err(SearchError::NoLegalMoves);
     }
 
-    let mut possible_moves: VecDeque<ChessMove> = MoveGen::new_legal(&board).collect();
-
-    while depth < target_depth + 1 {
+    let mut possible_moves: VecDeque<ChessMove> = legal_moves
+        .filter(|chess_move| {
+            limits.searchmoves.is_empty() || limits.searchmoves.contains(chess_move)
+        })
+        .collect();
+    let first_move = match possible_moves.front() {
+        Some(chess_move) => *chess_move,
+        None => return Err(SearchError::NoLegalSearchMoves),
+    };
+    let mut result = SearchResult {
+        best_move: first_move,
+        eval: 0.0,
+        depth: 0,
+        pv: vec![first_move],
+        lines: vec![],
+        nodes: 0,
+    };
+
+    for depth in 1..=target_depth {
         // the best moves from the last iteration are searched first to improve alpha-beta pruning performance
         debug!("Evaluating with depth {}", depth);
-        // Need to rethink... this may result in two copies of the transposition table at once
-        let mut scores = negamax_root(board, color_to_move, depth, possible_moves, tt.clone());
+        // Multiple lines need exact evals for more than just the best move, so they always get
+        // the full window
+        let mut delta = ASPIRATION_WINDOW;
+        let mut window = if depth >= ASPIRATION_MIN_DEPTH
+            && multipv == 1
+            && result.eval.abs() < MATE_THRESHOLD
+        {
+            (result.eval - delta, result.eval + delta)
+        } else {
+            (-f32::INFINITY, f32::INFINITY)
+        };
+        // Re-searches after a failed window count as the same iteration
+        let mut seldepth = 0;
+
+        let (mut scores, stats) = loop {
+            // Need to rethink... this may result in two copies of the transposition table at once
+            let (scores, stats) = negamax_root(
+                board,
+                depth,
+                possible_moves.clone(),
+                window,
+                multipv,
+                options,
+                tt.clone(),
+                &mut ordering,
+                &time_manager,
+                &control,
+                limits.nodes.map(|nodes| nodes.saturating_sub(result.nodes)),
+            );
+            result.nodes += stats.nodes;
+            seldepth = seldepth.max(stats.seldepth);
+
+            if stats.stopped {
+                break (scores, stats);
+            }
 
-        // Stop if you found checkmate
-        if scores[0].eval == 10000.0 {
-            return scores[0].chess_move;
+            let eval = scores[0].eval;
+            let bound = if eval <= window.0 {
+                delta *= 2.0;
+                window.0 = if delta > ASPIRATION_MAX_WINDOW {
+                    -f32::INFINITY
+                } else {
+                    eval - delta
+                };
+                Flag::Upperbound
+            } else if eval >= window.1 {
+                delta *= 2.0;
+                window.1 = if delta > ASPIRATION_MAX_WINDOW {
+                    f32::INFINITY
+                } else {
+                    eval + delta
+                };
+                Flag::Lowerbound
+            } else {
+                break (scores, stats);
+            };
+            debug!("Aspiration window failed at depth {}, widening to {:?}", depth, window);
+
+            on_info(&SearchInfo {
+                depth,
+                seldepth,
+                multipv: 1,
+                score: Score::from_eval(eval),
+                bound,
+                nodes: result.nodes,
+                hashfull: tt.hashfull(),
+                time: time_manager.elapsed(),
+                pv: scores[0].pv.clone(),
+            });
+        };
+
+        if stats.stopped {
+            // Last iteration's best move is searched first, so anything that beat it can be
+            // trusted. Unless nothing got above alpha, then none of the evals are worth anything.
+            if scores.iter().any(|score| score.chess_move == result.best_move)
+                && scores[0].eval > window.0
+            {
+                scores.truncate(multipv);
+                result.best_move = scores[0].chess_move;
+                result.eval = scores[0].eval;
+                result.pv = scores[0].pv.clone();
+                result.lines = scores;
+            }
+            debug!("Stopped during depth {}", depth);
+            break;
         }
 
-        let mut best_moves: VecDeque<ChessMove> = VecDeque::new();
-        scores.reverse();
-        for score in scores {
-            debug!("{}, {}", score.chess_move, score.eval);
-            best_moves.push_back(score.chess_move);
+        possible_moves = scores
+            .iter()
+            .map(|score| {
+                debug!("{}, {}", score.chess_move, score.eval);
+                score.chess_move
+            })
+            .collect();
+
+        scores.truncate(multipv);
+        result.best_move = scores[0].chess_move;
+        result.eval = scores[0].eval;
+        result.depth = depth;
+        result.pv = scores[0].pv.clone();
+        result.lines = scores;
+
+        for (i, line) in result.lines.iter().enumerate() {
+            on_info(&SearchInfo {
+                depth,
+                seldepth,
+                multipv: i + 1,
+                score: Score::from_eval(line.eval),
+                bound: Flag::Exact,
+                nodes: result.nodes,
+                hashfull: tt.hashfull(),
+                time: time_manager.elapsed(),
+                pv: line.pv.clone(),
+            });
         }
 
-        possible_moves = best_moves;
+        // Stop if you found checkmate, and every shorter one would have been found by now
+        if result.eval > MATE_THRESHOLD && CHECKMATE - result.eval <= depth as f32 {
+            break;
+        }
+
+        if let (Some(mate), Score::Mate(moves)) = (limits.mate, Score::from_eval(result.eval)) {
+            if moves > 0 && moves as u32 <= mate {
+                break;
+            }
+        }
 
-        depth += 1;
+        if time_manager.soft_limit_reached()
+            || limits.nodes.is_some_and(|nodes| result.nodes >= nodes)
+        {
+            break;
+        }
     }
 
-    *possible_moves
-        .get(possible_moves.len() - 1)
-        .expect("This is imepossible. There should be at least one possible move.")
+    Ok(result)
 }
 
+/// Totals over all of the threads of a single negamax_root call
+#[derive(Default)]
+struct RootStats {
+    nodes: u64,
+    seldepth: i32,
+    /// Set if the search had to stop before every move was evaluated
+    stopped: bool,
+}
+
+/// Returns the evaluated moves from best to worst, and whether the search had to stop early.
+/// Moves that weren't finished before stopping are left out. Only the best multipv moves are
+/// guaranteed to have exact evals, the rest only have to be shown to be worse than those.
+/// Nothing is exact outside of `window` though: if no move beats its alpha all evals are upper
+/// bounds, and a thread stops at the first move reaching its beta, which is only a lower bound.
+/// The node limit is shared evenly between the threads, `ordering` holds the move ordering tables
+/// of every thread and gets them back once the threads are done.
+#[allow(clippy::too_many_arguments)]
 fn negamax_root(
     board: Board,
-    color_to_move: Color,
     max_depth: i32,
     mut moves: VecDeque<ChessMove>,
-    tt: Arc<Mutex<TransTable>>,
-) -> Vec<MoveEval> {
+    window: (f32, f32),
+    multipv: usize,
+    options: &SearchOptions,
+    tt: Arc<TransTable>,
+    ordering: &mut Vec<Box<OrderingTables>>,
+    time_manager: &TimeManager,
+    control: &Arc<SearchControl>,
+    node_limit: Option<u64>,
+) -> (Vec<MoveEval>, RootStats) {
     // Returns moves in best to worst order
     let mut combined_evals: Vec<MoveEval> = vec![];
+    let mut stats = RootStats::default();
     let mut handles = vec![];
-    let mut thread_num = 0;
     // Should probably switch out for a custom implementation
 
     let work = threading::divide_work(&mut moves);
-
-    for thread_work in work {
-        let mut thread_local_tt = tt.clone();
+    let thread_node_limit = node_limit.map(|nodes| nodes / work.len() as u64);
+
+    for (thread_num, thread_work) in work.into_iter().enumerate() {
+        let mut search_thread = SearchThread::new(
+            tt.clone(),
+            time_manager.clone(),
+            control.clone(),
+            options,
+            ordering.pop().unwrap_or_else(OrderingTables::new),
+            max_depth,
+            thread_node_limit,
+        );
 
         let thread = thread::spawn(move || {
             let mut scores: Vec<MoveEval> = vec![];
-            let mut rng = SmallRng::from_entropy();
             let current_thread_num = thread_num;
 
-            let alpha: f32 = -f32::INFINITY;
-            let beta = f32::INFINITY;
+            let (mut alpha, beta) = window;
 
             for (i, possible_move) in thread_work.iter().enumerate() {
                 debug!("Thread {}: Evaluating {}/{} moves", current_thread_num, i, thread_work.len());
@@ -193,172 +764,783 @@ fn negamax_root(
 
                 // Check if it's a terminal node
                 if new_board.status() == chess::BoardStatus::Checkmate {
-                    // Return 10000 and +/- for how close to checkmate it is
+                    // Mate in one ply
                     let score = MoveEval {
                         chess_move: *possible_move,
-                        eval: 10000.0,
+                        eval: CHECKMATE - 1.0,
+                        pv: vec![*possible_move],
                     };
                     scores.push(score);
                     break;
                 } else if new_board.status() == chess::BoardStatus::Stalemate {
                     let score = MoveEval {
                         chess_move: *possible_move,
-                        eval: -1000.0,
+                        eval: 0.0,
+                        pv: vec![*possible_move],
                     };
                     scores.push(score);
                 } else {
-                    let evaluation = -negamax(
-                        new_board,
-                        max_depth,
-                        max_depth - 1,
-                        -beta,
-                        -alpha,
-                        utils::flip_color(color_to_move),
-                        thread_local_tt.clone(),
-                        &mut rng,
-                    );
+                    search_thread.moves_played[0] = Some(*possible_move);
+                    search_thread.captures_played[0] =
+                        move_ordering::captured_piece(&board, *possible_move).is_some();
+                    let evaluation =
+                        search_root(new_board, max_depth, alpha, beta, &mut search_thread);
 
+                    if search_thread.stopped {
+                        break;
+                    }
+
+                    let mut pv = vec![*possible_move];
+                    pv.extend_from_slice(search_thread.pv.line(1));
+                    extend_pv(
+                        board,
+                        &mut pv,
+                        &search_thread.tt,
+                        max_depth as usize,
+                    );
                     let score = MoveEval {
                         chess_move: *possible_move,
                         eval: evaluation,
+                        pv,
                     };
 
                     scores.push(score);
 
-                    let alpha = f32::max(alpha, evaluation);
-
-                    if alpha >= beta {
+                    if evaluation >= beta {
                         break;
                     }
                 }
+
+                alpha = multipv_alpha(&scores, multipv).max(window.0);
             }
 
-            scores
+            (scores, search_thread)
         });
 
         handles.push(thread);
-        thread_num += 1;
     }
 
     for handle in handles {
-        let mut output = handle.join().unwrap();
+        let (mut output, search_thread) = handle.join().unwrap();
         combined_evals.append(&mut output);
+        stats.nodes += search_thread.nodes;
+        stats.seldepth = i32::max(stats.seldepth, search_thread.seldepth);
+        stats.stopped |= search_thread.stopped;
+        ordering.push(search_thread.ordering);
     }
 
     // Sort from best to worst
     combined_evals.sort_by(|a, b| b.cmp(a));
 
-    combined_evals
+    (combined_evals, stats)
+}
+
+/// Evaluates the position after a root move with the selected algorithm, from the point of view
+/// of the side that played it. The eval only has to be exact if it's between alpha and beta.
+fn search_root(
+    board: Board,
+    max_depth: i32,
+    alpha: f32,
+    beta: f32,
+    thread: &mut SearchThread,
+) -> f32 {
+    let color = board.side_to_move();
+    let depth = max_depth - 1;
+
+    match thread.algorithm {
+        Algorithm::MinMax => -minmax_memory(board, 1, depth, thread),
+        Algorithm::ABPrune => -negamax(board, 1, depth, -beta, -alpha, color, thread),
+        Algorithm::PVS => {
+            // The first move has nothing to be compared against yet
+            if alpha == -f32::INFINITY {
+                return -negamax(board, 1, depth, -beta, -alpha, color, thread);
+            }
+
+            let score =
+                -negamax(board, 1, depth, -alpha - NULL_WINDOW, -alpha, color, thread);
+            if score > alpha && score < beta && !thread.stopped {
+                -negamax(board, 1, depth, -beta, -alpha, color, thread)
+            } else {
+                score
+            }
+        }
+        Algorithm::MTDF => mtdf(board, max_depth, alpha, beta, thread),
+    }
+}
+
+/// Closes in on the eval from both sides with null window searches, starting from the eval the
+/// transposition table has for this position. Gives up as soon as it's clear the eval isn't
+/// between alpha and beta, like a fail low or high. Pruning makes the null window searches
+/// contradict each other now and then, if that happens or they just don't converge, a normal
+/// search decides.
+fn mtdf(board: Board, max_depth: i32, alpha: f32, beta: f32, thread: &mut SearchThread) -> f32 {
+    let color = board.side_to_move();
+    let depth = max_depth - 1;
+    let mut lower = -f32::INFINITY;
+    let mut upper = f32::INFINITY;
+
+    let tt_eval = thread.tt.get(&board).map(|entry| -eval_from_tt(entry.eval, 1));
+    let mut guess = tt_eval.unwrap_or_else(|| match color {
+        Color::White => -evaluate::evaluate(board),
+        Color::Black => evaluate::evaluate(board),
+    });
+
+    for _ in 0..MTDF_MAX_PASSES {
+        // The window has to sit right above a proven lower bound, otherwise it proves nothing new
+        let test = if guess == lower { guess + NULL_WINDOW } else { guess };
+        guess = -negamax(board, 1, depth, -test, -test + NULL_WINDOW, color, thread);
+        if thread.stopped {
+            return 0.0;
+        }
+
+        if guess < test {
+            upper = guess;
+        } else {
+            lower = guess;
+        }
+
+        if upper <= alpha {
+            return upper;
+        }
+        if lower >= beta {
+            return lower;
+        }
+        if lower >= upper {
+            if lower - upper <= NULL_WINDOW {
+                return guess;
+            }
+            break;
+        }
+    }
+
+    -negamax(board, 1, depth, -beta, -alpha, color, thread)
+}
+
+/// Plain minimax without any pruning, every line gets searched all the way down. Only stores
+/// its exact evals in the transposition table and never reads from it, so it can serve as the
+/// reference the other algorithms are checked against.
+fn minmax_memory(
+    board: Board,
+    ply: i32,
+    current_depth: i32,
+    thread: &mut SearchThread,
+) -> f32 {
+    thread.nodes += 1;
+    thread.seldepth = i32::max(thread.seldepth, ply);
+    thread.pv.clear(ply as usize);
+    if thread.should_stop() {
+        return 0.0;
+    }
+
+    // Handle Checkmate and Stalemate the same way negamax does
+    match board.status() {
+        BoardStatus::Checkmate => return -(CHECKMATE - ply as f32),
+        BoardStatus::Stalemate => return 0.0,
+        BoardStatus::Ongoing => (),
+    }
+
+    if current_depth == 0 {
+        let color = board.side_to_move();
+        return quiescence(board, ply, -f32::INFINITY, f32::INFINITY, color, false, thread);
+    }
+
+    let mut eval = -f32::INFINITY;
+    let mut best_move = None;
+    for possible_move in MoveGen::new_legal(&board) {
+        let new_board = board.make_move_new(possible_move);
+        let score = -minmax_memory(new_board, ply + 1, current_depth - 1, thread);
+        if thread.stopped {
+            return 0.0;
+        }
+
+        if eval < score {
+            eval = score;
+            best_move = Some(possible_move);
+            thread.pv.update(ply as usize, possible_move);
+        }
+    }
+
+    let tt_entry = TransTableEntry {
+        depth: current_depth,
+        flag: Flag::Exact,
+        eval: eval_to_tt(eval, ply),
+        best_move,
+    };
+    thread.tt.add_entry(board, tt_entry);
+
+    eval
+}
+
+/// Lines cut short by a transposition table hit get finished with the best moves stored in the
+/// table, as long as those are legal and don't go round in circles.
+fn extend_pv(board: Board, pv: &mut Vec<ChessMove>, tt: &TransTable, max_length: usize) {
+    let mut board = pv
+        .iter()
+        .fold(board, |board, chess_move| board.make_move_new(*chess_move));
+    let mut seen = vec![board.get_hash()];
+
+    while pv.len() < max_length {
+        let best_move = match tt.get(&board).and_then(|entry| entry.best_move) {
+            Some(best_move) if board.legal(best_move) => best_move,
+            _ => break,
+        };
+
+        board = board.make_move_new(best_move);
+        if seen.contains(&board.get_hash()) {
+            break;
+        }
+        seen.push(board.get_hash());
+        pv.push(best_move);
+    }
+}
+
+/// Mates are counted in plies from the root, but the same position can come up at any ply.
+/// The transposition table counts them from the position itself instead.
+fn eval_to_tt(eval: f32, ply: i32) -> f32 {
+    if eval > MATE_THRESHOLD {
+        eval + ply as f32
+    } else if eval < -MATE_THRESHOLD {
+        eval - ply as f32
+    } else {
+        eval
+    }
+}
+
+/// Turns a mate from the transposition table back into one counted from the root.
+fn eval_from_tt(eval: f32, ply: i32) -> f32 {
+    if eval > MATE_THRESHOLD {
+        eval - ply as f32
+    } else if eval < -MATE_THRESHOLD {
+        eval + ply as f32
+    } else {
+        eval
+    }
+}
+
+/// Pieces of the side to move that aren't pawns or the king.
+fn non_pawn_pieces(board: &Board) -> u32 {
+    let pawns_and_kings = board.pieces(Piece::Pawn) | board.pieces(Piece::King);
+    (board.color_combined(board.side_to_move()) & !pawns_and_kings).popcnt()
+}
+
+/// A root move has to beat the multipv-th best eval so far to be one of the lines.
+fn multipv_alpha(scores: &[MoveEval], multipv: usize) -> f32 {
+    if scores.len() < multipv {
+        return -f32::INFINITY;
+    }
+
+    let mut evals: Vec<f32> = scores.iter().map(|score| score.eval).collect();
+    evals.sort_by(|a, b| b.total_cmp(a));
+    evals[multipv - 1]
 }
 
 fn negamax(
     current_board: chess::Board,
-    max_depth: i32,
+    ply: i32,
     current_depth: i32,
     mut alpha: f32,
     mut beta: f32,
     color: chess::Color,
-    tt: Arc<Mutex<TransTable>>,
-    rng: &mut SmallRng,
+    thread: &mut SearchThread,
 ) -> f32 {
+    thread.nodes += 1;
+    thread.seldepth = i32::max(thread.seldepth, ply);
+    thread.pv.clear(ply as usize);
+    if thread.should_stop() {
+        return 0.0;
+    }
+
     let alpha_original = alpha;
     let current_board_status = current_board.status();  
 
     // Check if it's a terminal node
     if current_board_status == chess::BoardStatus::Checkmate {
-        // Return 10000 and +/- for how close to checkmate it is
-        if current_board.side_to_move() == chess::Color::White {
-            return (10000 - current_depth) as f32;
-        } else {
-            return (-10000 + current_depth) as f32;
-        }
+        // The side to move got mated, the closer to the root the worse it is
+        return -(CHECKMATE - ply as f32);
     } else if current_board_status == chess::BoardStatus::Stalemate {
-        // Avoid stalemate at all costs but at less cost than checkmate
-        if current_board.side_to_move() == chess::Color::White {
-            return (-1000 + current_depth) as f32;
-        } else {
-            return (1000 - current_depth) as f32;
+        // A draw, worth the same to both sides no matter how it was reached
+        return 0.0;
+    }
+
+    // Extensions can only take a line this deep if something went very wrong, but there's no
+    // room to go any further
+    if ply as usize >= MAX_PLY - 1 {
+        return quiescence(current_board, ply, alpha, beta, color, false, thread);
+    }
+
+    let tt_entry = thread.tt.get(&current_board).map(|entry| TransTableEntry {
+        eval: eval_from_tt(entry.eval, ply),
+        ..entry
+    });
+    // Even when the entry is too shallow to trust its eval, its move is the best guess there is
+    let hash_move = tt_entry.and_then(|entry| entry.best_move);
+    // An entry that's almost as deep and says the hash move is at least this good is what
+    // singular extensions go by
+    let singular_eval = tt_entry
+        .filter(|entry| {
+            entry.flag != Flag::Upperbound
+                && entry.depth >= current_depth - SINGULAR_MAX_TT_DEPTH_GAP
+                && entry.eval.abs() < MATE_THRESHOLD
+        })
+        .map(|entry| entry.eval);
+
+    if let Some(entry) = tt_entry.filter(|entry| entry.depth >= current_depth) {
+        if entry.flag == Flag::Exact {
+            return entry.eval;
+        } else if entry.flag == Flag::Lowerbound {
+            alpha = f32::max(alpha, entry.eval);
+        } else if entry.flag == Flag::Upperbound {
+            beta = f32::min(beta, entry.eval);
+        }
+
+        if alpha >= beta {
+            return entry.eval;
         }
     }
 
-    let tt_entry = tt.lock().unwrap();
-    let tt_entry_unwrapped = tt_entry.tt.get(&current_board.get_hash());
+    if current_depth == 0 {
+        return quiescence(current_board, ply, alpha, beta, color, false, thread);
+    }
 
-    match tt_entry_unwrapped {
-        Some(entry) => {
-            if entry.depth >= max_depth {
-                if entry.flag == Flag::Exact {
-                    return entry.eval;
-                } else if entry.flag == Flag::Lowerbound {
-                    alpha = f32::max(alpha, entry.eval);
-                } else if entry.flag == Flag::Upperbound {
-                    beta = f32::min(beta, entry.eval);
-                }
+    let previous_move = thread.moves_played[ply as usize - 1];
+    let in_check = current_board.checkers().popcnt() > 0;
+    let static_eval = if color == chess::Color::White {
+        evaluate::evaluate(current_board)
+    } else {
+        -evaluate::evaluate(current_board)
+    };
+
+    // Pruning on the static eval can't be trusted in check, where it means nothing, or when a
+    // mate is on the line, which it knows nothing about
+    let prunable = thread.futility_pruning
+        && !in_check
+        && alpha.abs() < MATE_THRESHOLD
+        && beta.abs() < MATE_THRESHOLD
+        && static_eval.abs() < MATE_THRESHOLD;
+
+    // Reverse futility pruning: so far above beta that not even the opponent's best move is
+    // going to bring us back down in the few plies left. It makes the same bet as the null move
+    // though, so it stays off while a null move is being verified or zugzwang goes unnoticed again.
+    if prunable
+        && ply >= thread.null_move_min_ply
+        && current_depth <= REVERSE_FUTILITY_MAX_DEPTH
+        && static_eval - REVERSE_FUTILITY_MARGIN * current_depth as f32 >= beta
+    {
+        return static_eval;
+    }
 
-                if alpha >= beta {
-                    return entry.eval;
-                }
-            }
+    // Razoring: so far below alpha that only winning material or a mating attack could still
+    // save us, so only captures and checks get looked at. If they don't get us back up to alpha
+    // either, the position is given up on.
+    if prunable
+        && current_depth <= RAZORING_MAX_DEPTH
+        && static_eval + RAZORING_MARGIN * current_depth as f32 <= alpha
+    {
+        let score = quiescence(current_board, ply, alpha, beta, color, true, thread);
+        if thread.stopped {
+            return 0.0;
+        }
+        if score <= alpha {
+            return score;
         }
-        None => {}
     }
 
-    drop(tt_entry);
+    // Null move pruning: if we are still above beta after passing, a real move would almost
+    // certainly be as well. Passing isn't possible in check, and in pawn endings it's often the
+    // only "move" that doesn't lose (zugzwang), so neither gets one. Neither does the node right
+    // after a null move, that would just give the move back.
+    if current_depth >= NULL_MOVE_MIN_DEPTH
+        && !in_check
+        && previous_move.is_some()
+        && ply >= thread.null_move_min_ply
+        && beta.abs() < MATE_THRESHOLD
+        && static_eval >= beta
+        && non_pawn_pieces(&current_board) > 0
+    {
+        if let Some(null_board) = current_board.null_move() {
+            // The deeper the search, the more we can get away with skipping
+            let reduction = if current_depth > 6 { 3 } else { 2 };
+            let depth = (current_depth - 1 - reduction).max(0);
+
+            thread.moves_played[ply as usize] = None;
+            let null_score = -negamax(
+                null_board,
+                ply + 1,
+                depth,
+                -beta,
+                -beta + NULL_WINDOW,
+                utils::flip_color(color),
+                thread,
+            );
+            if thread.stopped {
+                return 0.0;
+            }
 
-    // Negamax algorithm requires that evaluations be returned relative to the side being evaluated
-    if current_depth == 0 {
-        if color == chess::Color::White {
-            return evaluate::evaluate(current_board);
-        } else {
-            return -evaluate::evaluate(current_board);
+            if null_score >= beta {
+                // Mates that rely on the opponent passing aren't real
+                let null_score = if null_score >= MATE_THRESHOLD { beta } else { null_score };
+
+                // With only a few pieces left the null move may only have failed high because
+                // every real move makes things worse, so the position itself has to prove it by
+                // failing high too, without any null moves of its own for a while
+                if non_pawn_pieces(&current_board) > NULL_MOVE_VERIFICATION_PIECES {
+                    return null_score;
+                }
+
+                let null_move_min_ply = thread.null_move_min_ply;
+                thread.null_move_min_ply = ply + 3 * depth / 4 + 1;
+                let verified =
+                    negamax(current_board, ply, depth, beta - NULL_WINDOW, beta, color, thread);
+                thread.null_move_min_ply = null_move_min_ply;
+                if thread.stopped {
+                    return 0.0;
+                }
+
+                if verified >= beta {
+                    return null_score;
+                }
+                // The verification search left its line behind
+                thread.pv.clear(ply as usize);
+            }
         }
     }
 
-    // Eventually use algorithm to sort them by potential to save time
-    let possible_moves = MoveGen::new_legal(&current_board);
+    let mut possible_moves =
+        MovePicker::new(&current_board, ply as usize, hash_move, previous_move);
     let mut value = -f32::INFINITY;
+    // Nothing raising alpha doesn't make the old hash move any worse of a guess
+    let mut best_move = hash_move;
+    let mut quiets_tried: Vec<ChessMove> = vec![];
+    let mut moves_searched: usize = 0;
+
+    while let Some(possible_move) = possible_moves.next(&thread.ordering) {
+        let quiet = move_ordering::is_quiet(&current_board, possible_move);
+        let new_board = current_board.make_move_new(possible_move);
+        let new_color = utils::flip_color(color);
+        let gives_check = new_board.checkers().popcnt() > 0;
+
+        // Late move pruning: close to the horizon, the quiet moves that come this late are
+        // hardly ever any good. Only once we know we aren't getting mated, though, and checks
+        // might just be mate.
+        if thread.late_move_reductions
+            && quiet
+            && !in_check
+            && !gives_check
+            && current_depth <= LMP_MAX_DEPTH
+            && value > -MATE_THRESHOLD
+            && quiets_tried.len() >= (3 + current_depth * current_depth) as usize
+        {
+            continue;
+        }
+
+        // Futility pruning: a quiet move can't make up for being this far below alpha this
+        // close to the horizon. Anything it could have been worth is still counted, so the
+        // value stays an upper bound.
+        let futility_value = static_eval + FUTILITY_MARGIN * current_depth as f32;
+        if prunable
+            && quiet
+            && !gives_check
+            && current_depth <= FUTILITY_MAX_DEPTH
+            && value > -f32::INFINITY
+            && futility_value <= alpha
+        {
+            value = f32::max(value, futility_value);
+            continue;
+        }
 
-    for possible_move in possible_moves {
-        value = f32::max(
-            value,
-            -negamax(
-                current_board.make_move_new(possible_move),
-                max_depth,
-                current_depth - 1,
+        // Extensions: checks, recaptures and pawns about to promote are forcing enough that
+        // the horizon shouldn't cut them off halfway, and neither should it the hash move when
+        // it's the only move that holds. A pawn on the seventh is always a passed pawn, there's
+        // nowhere left for pawns to stop it from.
+        let capture = move_ordering::captured_piece(&current_board, possible_move).is_some();
+        let extend = thread.extensions
+            && thread.extended < thread.max_extensions
+            && (gives_check
+                || (capture
+                    && thread.captures_played[ply as usize - 1]
+                    && previous_move.is_some_and(|previous| {
+                        previous.get_dest() == possible_move.get_dest()
+                    }))
+                || (current_board.piece_on(possible_move.get_source()) == Some(Piece::Pawn)
+                    && possible_move.get_dest().get_rank()
+                        == match color {
+                            Color::White => Rank::Seventh,
+                            Color::Black => Rank::Second,
+                        })
+                || (Some(possible_move) == hash_move
+                    && current_depth >= SINGULAR_MIN_DEPTH
+                    && singular_eval.is_some_and(|eval| {
+                        is_singular(
+                            current_board,
+                            ply,
+                            (current_depth - 1) / 2,
+                            possible_move,
+                            eval - SINGULAR_MARGIN,
+                            color,
+                            thread,
+                        )
+                    })));
+        if thread.stopped {
+            return 0.0;
+        }
+        let extension = extend as i32;
+        let new_depth = current_depth - 1 + extension;
+
+        thread.moves_played[ply as usize] = Some(possible_move);
+        thread.captures_played[ply as usize] = capture;
+        thread.extended += extension;
+
+        let mut score = -f32::INFINITY;
+        let mut full_depth = true;
+
+        // Late move reductions: quiet moves and captures that lose material that come late are
+        // most likely bad, so they only get a reduced null window search. If that beats alpha
+        // they were better than they looked and get searched at full depth after all.
+        if thread.late_move_reductions
+            && !in_check
+            && !gives_check
+            && current_depth >= LMR_MIN_DEPTH
+            && moves_searched >= LMR_MIN_MOVES
+            && (quiet || see::see(&current_board, possible_move) < 0)
+        {
+            let reduction = thread.reductions[(current_depth as usize).min(LMR_TABLE_SIZE - 1)]
+                [moves_searched.min(LMR_TABLE_SIZE - 1)];
+            if reduction > 0 {
+                let depth = (new_depth - reduction).max(1);
+                score = -negamax(
+                    new_board,
+                    ply + 1,
+                    depth,
+                    -alpha - NULL_WINDOW,
+                    -alpha,
+                    new_color,
+                    thread,
+                );
+                full_depth = score > alpha && !thread.stopped;
+            }
+        }
+        moves_searched += 1;
+
+        // PVS only searches the first move with the full window, the rest just have to be shown
+        // not to beat it. The ones that do beat it get searched again to find out by how much.
+        let mut full_window = full_depth;
+        if full_depth && thread.algorithm == Algorithm::PVS && value > -f32::INFINITY {
+            score = -negamax(
+                new_board,
+                ply + 1,
+                new_depth,
+                -alpha - NULL_WINDOW,
+                -alpha,
+                new_color,
+                thread,
+            );
+            full_window = score > alpha && score < beta && !thread.stopped;
+        }
+        if full_window {
+            score = -negamax(
+                new_board,
+                ply + 1,
+                new_depth,
                 -beta,
                 -alpha,
-                utils::flip_color(color),
-                tt.clone(),
-                rng,
-            ),
-        );
+                new_color,
+                thread,
+            );
+        }
+        thread.extended -= extension;
 
-        alpha = f32::max(alpha, value);
+        // The value of a half searched subtree can't be trusted
+        if thread.stopped {
+            return 0.0;
+        }
+
+        if score > value {
+            value = score;
+
+            if value > alpha {
+                alpha = value;
+                best_move = Some(possible_move);
+                thread.pv.update(ply as usize, possible_move);
+            }
+        }
 
         if alpha >= beta {
+            if quiet {
+                thread.ordering.record_cutoff(
+                    &current_board,
+                    ply as usize,
+                    current_depth,
+                    possible_move,
+                    previous_move,
+                    &quiets_tried,
+                );
+            }
             break;
         }
+
+        if quiet {
+            quiets_tried.push(possible_move);
+        }
     }
 
-    // Don't write checkmates into the transposition table
-    if !(-9000.0..=9000.0).contains(&value) {
-        let flag: Flag;
-        if value <= alpha_original {
-            flag = Flag::Upperbound;
-        } else if value >= beta {
-            flag = Flag::Lowerbound;
-        } else {
-            flag = Flag::Exact;
+    let flag: Flag;
+    if value <= alpha_original {
+        flag = Flag::Upperbound;
+    } else if value >= beta {
+        flag = Flag::Lowerbound;
+    } else {
+        flag = Flag::Exact;
+    }
+
+    let tt_entry = TransTableEntry {
+        depth: current_depth,
+        flag,
+        eval: eval_to_tt(value, ply),
+        best_move,
+    };
+
+    thread.tt.add_entry(current_board, tt_entry);
+
+    value
+}
+
+/// Whether every move besides `hash_move` stays below `singular_beta`, searched `depth` plies
+/// deep with a null window. If so the hash move is the only one that holds the position.
+fn is_singular(
+    board: Board,
+    ply: i32,
+    depth: i32,
+    hash_move: ChessMove,
+    singular_beta: f32,
+    color: Color,
+    thread: &mut SearchThread,
+) -> bool {
+    let previous_move = thread.moves_played[ply as usize - 1];
+    let mut moves = MovePicker::new(&board, ply as usize, Some(hash_move), previous_move);
+
+    while let Some(chess_move) = moves.next(&thread.ordering) {
+        // Not necessarily the first move, it's left out if a hash collision made it illegal
+        if chess_move == hash_move {
+            continue;
         }
+        thread.moves_played[ply as usize] = Some(chess_move);
+        thread.captures_played[ply as usize] =
+            move_ordering::captured_piece(&board, chess_move).is_some();
+        let score = -negamax(
+            board.make_move_new(chess_move),
+            ply + 1,
+            depth,
+            -singular_beta,
+            -singular_beta + NULL_WINDOW,
+            utils::flip_color(color),
+            thread,
+        );
 
-        let tt_entry = TransTableEntry {
-            depth: max_depth - current_depth + 1,
-            flag,
-            eval: value,
-        };
+        if thread.stopped || score >= singular_beta {
+            return false;
+        }
+    }
+
+    true
+}
+
+/// Keeps searching captures and promotions past the horizon until the position is quiet, so the
+/// eval is never taken halfway through an exchange. The side to move can always stand pat
+/// instead, unless it's in check, then every evasion is searched. With `checks` the quiet moves
+/// that give check are searched too, so mates right behind the horizon aren't missed.
+fn quiescence(
+    board: Board,
+    ply: i32,
+    mut alpha: f32,
+    beta: f32,
+    color: Color,
+    checks: bool,
+    thread: &mut SearchThread,
+) -> f32 {
+    thread.nodes += 1;
+    thread.seldepth = i32::max(thread.seldepth, ply);
+    if thread.should_stop() {
+        return 0.0;
+    }
+
+    let in_check = board.checkers().popcnt() > 0;
+    let legal_moves = MoveGen::new_legal(&board);
+    if legal_moves.len() == 0 {
+        if in_check {
+            return -(CHECKMATE - ply as f32);
+        }
+        return 0.0;
+    }
+
+    // Negamax algorithm requires that evaluations be returned relative to the side being evaluated
+    let stand_pat = if color == Color::White {
+        evaluate::evaluate(board)
+    } else {
+        -evaluate::evaluate(board)
+    };
+    if ply as usize >= MAX_PLY - 1 {
+        return stand_pat;
+    }
 
-        tt.lock().unwrap().add_entry(current_board, tt_entry);
+    let mut value = -f32::INFINITY;
+    let mut moves: Vec<ChessMove> = if in_check {
+        legal_moves.collect()
+    } else {
+        if stand_pat >= beta {
+            return stand_pat;
+        }
+        value = stand_pat;
+        alpha = f32::max(alpha, stand_pat);
+
+        legal_moves
+            .filter(|chess_move| {
+                !move_ordering::is_quiet(&board, *chess_move)
+                    || (checks && board.make_move_new(*chess_move).checkers().popcnt() > 0)
+            })
+            .collect()
+    };
+    moves.sort_by_key(|chess_move| -move_ordering::mvv_lva(&board, *chess_move));
+
+    for chess_move in moves {
+        if !in_check {
+            if let Some(victim) = move_ordering::captured_piece(&board, chess_move) {
+                // Delta pruning, not even winning the piece for free would get us back up to alpha
+                let victim = evaluate::PIECE_VALUES[victim.to_index()] as f32 / 100.0;
+                if chess_move.get_promotion().is_none()
+                    && stand_pat + victim + DELTA_MARGIN <= alpha
+                {
+                    continue;
+                }
+                // Captures that lose material can't do better than standing pat
+                if see::see(&board, chess_move) < 0 {
+                    continue;
+                }
+            }
+        }
+
+        let score = -quiescence(
+            board.make_move_new(chess_move),
+            ply + 1,
+            -beta,
+            -alpha,
+            utils::flip_color(color),
+            false,
+            thread,
+        );
+
+        if thread.stopped {
+            return 0.0;
+        }
+
+        if score > value {
+            value = score;
+            alpha = f32::max(alpha, value);
+        }
+
+        if alpha >= beta {
+            break;
+        }
     }
 
     value
-}
\ No newline at end of file
+}
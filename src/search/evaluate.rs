@@ -4,9 +4,7 @@
 //! that the possible moves decrease from the loss of a bishop may compensate for that. Each additional move would add 0.1
 //! The randomness is added so that moves with the same eval can be chosen randomly.
 
-use chess::{BitBoard, ChessMove, Color, MoveGen, Piece, Square, ALL_SQUARES, EMPTY};
-use log::debug;
-use rand::{prelude::SmallRng, Rng, SeedableRng};
+use chess::Color;
 
 // This  implements Piece Square Tables (PSQT) for each piece type. The
 // PSQT's are written from White's point of view, as if looking at a chess
@@ -15,6 +13,9 @@ use rand::{prelude::SmallRng, Rng, SeedableRng};
 
 type Psqt = [i32; 64];
 
+/// In centipawns, in the order of pawn, knight, bishop, rook, queen, king
+pub const PIECE_VALUES: [i32; 6] = [100, 300, 310, 500, 1200, 0];
+
 #[rustfmt::skip]
 const KING_MG: Psqt = [
     0,    0,     0,     0,    0,    0,    0,    0,
@@ -113,9 +114,6 @@ pub fn evaluate(board: chess::Board) -> f32 {
     // In the order white, black
     let mut color_eval: [i32; 2] = [0, 0];
 
-    // In the order of pawn, knight, bishop, root, queen, king
-    let piece_values: [i32;6] = [100, 300, 310, 500, 1200, 0];
-
     for color in chess::ALL_COLORS {
         let color_bitboard = board.color_combined(color);
         let mut color_specific_eval: i32 = 0;
@@ -124,7 +122,7 @@ pub fn evaluate(board: chess::Board) -> f32 {
             let piece_bitboard = board.pieces(*piece);
             // Looks for pieces of that type of that color
             let num_of_pieces_of_type = piece_bitboard & color_bitboard;
-            color_specific_eval += num_of_pieces_of_type.popcnt() as i32 * piece_values[i];
+            color_specific_eval += num_of_pieces_of_type.popcnt() as i32 * PIECE_VALUES[i];
             let mut piece_int = num_of_pieces_of_type.0;
             for _ in 0..piece_int.count_ones() {
                 color_specific_eval += PIECE_TABLE_ARRAY[i][FLIP[64*color.to_index()+piece_int.leading_zeros() as usize]];
@@ -0,0 +1,276 @@
+//! Guesses which moves are most likely to be good, so alpha-beta gets to cut the rest off.
+//! Moves are tried in stages: the hash move, then captures and promotions by MVV-LVA (the most
+//! valuable victim first, and of those the capture with the least valuable attacker first),
+//! then the killer moves, the counter-move, the remaining quiet moves by history and finally the
+//! captures that lose material according to static exchange evaluation.
+//! The legal moves are all generated up front, but a stage is only scored (and its captures
+//! only run through SEE) once every move of the earlier ones has been searched, so a cutoff by
+//! the hash move or a good capture saves that work for the rest.
+//! The tables behind the quiet move stages belong to a single search thread and are kept
+//! between iterations, what got cut off at depth n is a good guess for depth n + 1.
+
+use chess::{Board, ChessMove, MoveGen, Piece};
+
+use super::evaluate::PIECE_VALUES;
+use super::see::see;
+use super::MAX_PLY;
+
+// History scores get halved once one of them reaches this, so old cutoffs fade out before they
+// can overflow
+const HISTORY_MAX: i32 = 100_000;
+
+/// The piece a move takes, en passant included.
+pub fn captured_piece(board: &Board, chess_move: ChessMove) -> Option<Piece> {
+    match board.piece_on(chess_move.get_dest()) {
+        Some(piece) => Some(piece),
+        // A pawn moving diagonally onto an empty square can only be taking en passant
+        None if board.piece_on(chess_move.get_source()) == Some(Piece::Pawn)
+            && chess_move.get_source().get_file() != chess_move.get_dest().get_file() =>
+        {
+            Some(Piece::Pawn)
+        }
+        None => None,
+    }
+}
+
+/// Anything that doesn't change the material on the board.
+pub fn is_quiet(board: &Board, chess_move: ChessMove) -> bool {
+    chess_move.get_promotion().is_none() && captured_piece(board, chess_move).is_none()
+}
+
+/// Higher is searched first. Promotions count as winning the difference to the pawn.
+pub fn mvv_lva(board: &Board, chess_move: ChessMove) -> i32 {
+    let victim =
+        captured_piece(board, chess_move).map_or(0, |piece| PIECE_VALUES[piece.to_index()]);
+    let attacker = board
+        .piece_on(chess_move.get_source())
+        .map_or(0, |piece| piece.to_index() as i32);
+    let promotion = chess_move.get_promotion().map_or(0, |piece| {
+        PIECE_VALUES[piece.to_index()] - PIECE_VALUES[Piece::Pawn.to_index()]
+    });
+
+    // Piece values are far enough apart that the attacker only ever breaks ties
+    (victim + promotion) * 8 - attacker
+}
+
+/// What a search thread has learned about quiet moves so far
+pub struct OrderingTables {
+    /// The last two quiet moves that caused a cutoff at every ply
+    killers: [[Option<ChessMove>; 2]; MAX_PLY],
+    /// The quiet move that last refuted a move, indexed by that move's from and to squares
+    counter_moves: [[Option<ChessMove>; 64]; 64],
+    /// How well quiet moves did, indexed by side to move, from and to squares
+    history: [[[i32; 64]; 64]; 2],
+}
+
+impl OrderingTables {
+    pub fn new() -> Box<Self> {
+        Box::new(OrderingTables {
+            killers: [[None; 2]; MAX_PLY],
+            counter_moves: [[None; 64]; 64],
+            history: [[[0; 64]; 64]; 2],
+        })
+    }
+
+    /// Remembers the quiet move that caused a cutoff. The quiet moves that were searched before
+    /// it without causing one are moved down in the history instead.
+    pub fn record_cutoff(
+        &mut self,
+        board: &Board,
+        ply: usize,
+        depth: i32,
+        chess_move: ChessMove,
+        previous_move: Option<ChessMove>,
+        quiets_tried: &[ChessMove],
+    ) {
+        if self.killers[ply][0] != Some(chess_move) {
+            self.killers[ply][1] = self.killers[ply][0];
+            self.killers[ply][0] = Some(chess_move);
+        }
+
+        if let Some(previous) = previous_move {
+            self.counter_moves[previous.get_source().to_index()][previous.get_dest().to_index()] =
+                Some(chess_move);
+        }
+
+        // Deep cutoffs are worth a lot more than the ones right at the horizon
+        let bonus = depth * depth;
+        let side = board.side_to_move().to_index();
+        for quiet in quiets_tried {
+            let score =
+                &mut self.history[side][quiet.get_source().to_index()][quiet.get_dest().to_index()];
+            *score = (*score - bonus).max(-HISTORY_MAX);
+        }
+        let entry = &mut self.history[side][chess_move.get_source().to_index()]
+            [chess_move.get_dest().to_index()];
+        *entry += bonus;
+
+        if entry.abs() >= HISTORY_MAX {
+            for score in self.history.iter_mut().flatten().flatten() {
+                *score /= 2;
+            }
+        }
+    }
+}
+
+/// Hands out the legal moves of a position best guess first, one stage at a time.
+pub struct MovePicker {
+    board: Board,
+    ply: usize,
+    hash_move: Option<ChessMove>,
+    previous_move: Option<ChessMove>,
+    stage: Stage,
+    /// Every legal move, moves suggested by the hash table or the ordering tables are checked
+    /// against it
+    legal_moves: Vec<ChessMove>,
+    /// The moves of the current stage that are left, with their scores
+    moves: Vec<(ChessMove, i32)>,
+    /// Captures SEE says lose material, by MVV-LVA
+    losing_captures: Vec<(ChessMove, i32)>,
+    /// Killers and counter-move already handed out, so the quiet stage can skip them
+    refutations: Vec<ChessMove>,
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+enum Stage {
+    HashMove,
+    ScoreCaptures,
+    Captures,
+    Refutations,
+    ScoreQuiets,
+    Quiets,
+    LosingCaptures,
+    Done,
+}
+
+impl MovePicker {
+    /// `previous_move` is the move that led to `board`, if any. The hash move is only handed out
+    /// if it's legal, it can come from a different position that happens to share the hash.
+    pub fn new(
+        board: &Board,
+        ply: usize,
+        hash_move: Option<ChessMove>,
+        previous_move: Option<ChessMove>,
+    ) -> Self {
+        let legal_moves: Vec<ChessMove> = MoveGen::new_legal(board).collect();
+
+        MovePicker {
+            board: *board,
+            ply,
+            hash_move: hash_move.filter(|chess_move| legal_moves.contains(chess_move)),
+            previous_move,
+            stage: Stage::HashMove,
+            legal_moves,
+            moves: vec![],
+            losing_captures: vec![],
+            refutations: vec![],
+        }
+    }
+
+    /// The next move to search, None once every legal move has been handed out. `tables` are
+    /// only read when their stage comes up, so they may change in between calls.
+    pub fn next(&mut self, tables: &OrderingTables) -> Option<ChessMove> {
+        loop {
+            match self.stage {
+                Stage::HashMove => {
+                    self.stage = Stage::ScoreCaptures;
+                    if self.hash_move.is_some() {
+                        return self.hash_move;
+                    }
+                }
+                Stage::ScoreCaptures => {
+                    self.score_captures();
+                    self.stage = Stage::Captures;
+                }
+                Stage::Captures => match pop_best(&mut self.moves) {
+                    Some((chess_move, score)) => {
+                        if see(&self.board, chess_move) >= 0 {
+                            return Some(chess_move);
+                        }
+                        self.losing_captures.push((chess_move, score));
+                    }
+                    None => self.stage = Stage::Refutations,
+                },
+                Stage::Refutations => {
+                    if let Some(chess_move) = self.next_refutation(tables) {
+                        self.refutations.push(chess_move);
+                        return Some(chess_move);
+                    }
+                    self.stage = Stage::ScoreQuiets;
+                }
+                Stage::ScoreQuiets => {
+                    self.score_quiets(tables);
+                    self.stage = Stage::Quiets;
+                }
+                Stage::Quiets => match pop_best(&mut self.moves) {
+                    Some((chess_move, _)) => return Some(chess_move),
+                    None => self.stage = Stage::LosingCaptures,
+                },
+                Stage::LosingCaptures => match pop_best(&mut self.losing_captures) {
+                    Some((chess_move, _)) => return Some(chess_move),
+                    None => self.stage = Stage::Done,
+                },
+                Stage::Done => return None,
+            }
+        }
+    }
+
+    /// Captures and promotions by MVV-LVA, without the hash move.
+    fn score_captures(&mut self) {
+        let board = &self.board;
+        let hash_move = self.hash_move;
+
+        self.moves = self
+            .legal_moves
+            .iter()
+            .filter(|&&chess_move| Some(chess_move) != hash_move && !is_quiet(board, chess_move))
+            .map(|&chess_move| (chess_move, mvv_lva(board, chess_move)))
+            .collect();
+    }
+
+    /// The killers, then the counter-move, as long as they are legal quiet moves here and haven't
+    /// been handed out yet.
+    fn next_refutation(&self, tables: &OrderingTables) -> Option<ChessMove> {
+        let counter_move = self.previous_move.and_then(|previous| {
+            tables.counter_moves[previous.get_source().to_index()][previous.get_dest().to_index()]
+        });
+        let [first_killer, second_killer] = tables.killers[self.ply];
+
+        [first_killer, second_killer, counter_move]
+            .into_iter()
+            .flatten()
+            .find(|chess_move| {
+                Some(*chess_move) != self.hash_move
+                    && !self.refutations.contains(chess_move)
+                    && is_quiet(&self.board, *chess_move)
+                    && self.legal_moves.contains(chess_move)
+            })
+    }
+
+    /// Every quiet move left, by history.
+    fn score_quiets(&mut self, tables: &OrderingTables) {
+        let board = &self.board;
+        let side = board.side_to_move().to_index();
+
+        self.moves = self
+            .legal_moves
+            .iter()
+            .filter(|&&chess_move| {
+                Some(chess_move) != self.hash_move
+                    && !self.refutations.contains(&chess_move)
+                    && is_quiet(board, chess_move)
+            })
+            .map(|&chess_move| {
+                let score = tables.history[side][chess_move.get_source().to_index()]
+                    [chess_move.get_dest().to_index()];
+                (chess_move, score)
+            })
+            .collect();
+    }
+}
+
+/// Takes the highest scored move out of `moves`, without sorting the rest.
+fn pop_best(moves: &mut Vec<(ChessMove, i32)>) -> Option<(ChessMove, i32)> {
+    let best = (0..moves.len()).max_by_key(|&i| moves[i].1)?;
+    Some(moves.swap_remove(best))
+}
@@ -0,0 +1,46 @@
+//! Triangular principal variation table. Every ply gets a row holding the best line found from
+//! that ply on, and whenever a move raises alpha the line below it gets copied up behind it.
+//! Lines get cut short by transposition table cutoffs, there's nothing to copy from those.
+
+use chess::ChessMove;
+
+use super::MAX_PLY;
+
+pub struct PvTable {
+    lines: Vec<[ChessMove; MAX_PLY]>,
+    lengths: [usize; MAX_PLY],
+}
+
+impl PvTable {
+    pub fn new() -> Self {
+        PvTable {
+            lines: vec![[ChessMove::default(); MAX_PLY]; MAX_PLY],
+            lengths: [0; MAX_PLY],
+        }
+    }
+
+    /// Has to be called when entering a node, so a leaf doesn't inherit an old line.
+    pub fn clear(&mut self, ply: usize) {
+        self.lengths[ply] = ply;
+    }
+
+    /// The best line from `ply` on now starts with `chess_move`.
+    pub fn update(&mut self, ply: usize, chess_move: ChessMove) {
+        self.lines[ply][ply] = chess_move;
+
+        let child_length = if ply + 1 < MAX_PLY {
+            self.lengths[ply + 1].max(ply + 1)
+        } else {
+            ply + 1
+        };
+
+        for i in ply + 1..child_length {
+            self.lines[ply][i] = self.lines[ply + 1][i];
+        }
+        self.lengths[ply] = child_length;
+    }
+
+    pub fn line(&self, ply: usize) -> &[ChessMove] {
+        &self.lines[ply][ply..self.lengths[ply]]
+    }
+}
@@ -0,0 +1,110 @@
+//! Static exchange evaluation: plays out every capture on a single square, always with the
+//! least valuable attacker left, and tells whether starting the exchange wins or loses material.
+//! Either side can stop capturing whenever going on would only make things worse. Sliders lined
+//! up behind the pieces that capture first (x-rays) join in once those are out of the way.
+//! Pins and checks are ignored, which makes it a guess, but a cheap and mostly right one.
+
+use chess::{
+    get_bishop_moves, get_king_moves, get_knight_moves, get_pawn_attacks, get_rook_moves, BitBoard,
+    Board, ChessMove, Color, Piece, Rank, Square, ALL_PIECES, EMPTY,
+};
+
+use super::evaluate::PIECE_VALUES;
+use super::move_ordering::captured_piece;
+
+/// Material won (in centipawns) by the side to move if it plays `chess_move` and both sides keep
+/// recapturing on its destination for as long as it pays off. Negative if the move loses material.
+pub fn see(board: &Board, chess_move: ChessMove) -> i32 {
+    let source = chess_move.get_source();
+    let dest = chess_move.get_dest();
+    let mut occupied = *board.combined() ^ BitBoard::from_square(source);
+
+    let victim = captured_piece(board, chess_move);
+    if victim.is_some() && board.piece_on(dest).is_none() {
+        // En passant, the pawn being taken isn't on the destination square
+        let taken = Square::make_square(source.get_rank(), dest.get_file());
+        occupied ^= BitBoard::from_square(taken);
+    }
+
+    let mut on_square = match chess_move.get_promotion() {
+        Some(promotion) => promotion,
+        None => board.piece_on(source).unwrap_or(Piece::Pawn),
+    };
+    // What each capture in the sequence wins, if the exchange stopped right after it
+    let mut gains = vec![value(victim) + promotion_gain(chess_move.get_promotion())];
+    let mut side = !board.side_to_move();
+
+    loop {
+        let attackers = attackers_to(board, dest, occupied) & occupied;
+        let (square, piece) = match least_valuable(board, attackers & board.color_combined(side)) {
+            Some(attacker) => attacker,
+            None => break,
+        };
+        // The king can only take if nothing is left to take it back
+        if piece == Piece::King && attackers & board.color_combined(!side) != EMPTY {
+            break;
+        }
+
+        let last = *gains.last().unwrap();
+        gains.push(PIECE_VALUES[on_square.to_index()] - last);
+
+        on_square = piece;
+        if piece == Piece::Pawn && dest.get_rank() == promotion_rank(side) {
+            *gains.last_mut().unwrap() += promotion_gain(Some(Piece::Queen));
+            on_square = Piece::Queen;
+        }
+        occupied ^= BitBoard::from_square(square);
+        side = !side;
+    }
+
+    // Going back from the end of the exchange, every side either takes or stops right there,
+    // whichever is better for it
+    for i in (1..gains.len()).rev() {
+        gains[i - 1] = -i32::max(-gains[i - 1], gains[i]);
+    }
+    gains[0]
+}
+
+/// Every piece of either color attacking `square`, with only the pieces in `occupied` blocking
+/// the sliders.
+fn attackers_to(board: &Board, square: Square, occupied: BitBoard) -> BitBoard {
+    let pawns = board.pieces(Piece::Pawn);
+    let diagonal = board.pieces(Piece::Bishop) | board.pieces(Piece::Queen);
+    let straight = board.pieces(Piece::Rook) | board.pieces(Piece::Queen);
+
+    // A pawn attacks a square if a pawn of the other color on that square would attack it
+    let white_pawns = pawns & board.color_combined(Color::White);
+    let black_pawns = pawns & board.color_combined(Color::Black);
+    let pawns = get_pawn_attacks(square, Color::Black, white_pawns)
+        | get_pawn_attacks(square, Color::White, black_pawns);
+
+    pawns
+        | (get_knight_moves(square) & board.pieces(Piece::Knight))
+        | (get_king_moves(square) & board.pieces(Piece::King))
+        | (get_bishop_moves(square, occupied) & diagonal)
+        | (get_rook_moves(square, occupied) & straight)
+}
+
+/// The cheapest of `attackers`, the king last.
+fn least_valuable(board: &Board, attackers: BitBoard) -> Option<(Square, Piece)> {
+    // ALL_PIECES is ordered from the pawn up to the king
+    ALL_PIECES.iter().find_map(|&piece| {
+        let candidates = attackers & board.pieces(piece);
+        (candidates != EMPTY).then(|| (candidates.to_square(), piece))
+    })
+}
+
+fn value(piece: Option<Piece>) -> i32 {
+    piece.map_or(0, |piece| PIECE_VALUES[piece.to_index()])
+}
+
+fn promotion_gain(promotion: Option<Piece>) -> i32 {
+    promotion.map_or(0, |piece| value(Some(piece)) - value(Some(Piece::Pawn)))
+}
+
+fn promotion_rank(color: Color) -> Rank {
+    match color {
+        Color::White => Rank::Eighth,
+        Color::Black => Rank::First,
+    }
+}
@@ -12,6 +12,7 @@ pub fn divide_work(possible_moves: &mut VecDeque<ChessMove>) -> Vec<Vec<ChessMov
 
     // Could use improvements in the future, since the first thread may evaluate much faster
     // than the second and third threads due to move ordering improvements.
+    #[allow(clippy::reversed_empty_ranges)]
     for _ in 0..THREADS - 1 {
         let mut thread_work = vec![];
         let mut num_to_run = num_per_thread;
@@ -0,0 +1,101 @@
+//! Decides how long a search is allowed to take. The soft limit is checked between iterations
+//! (no point starting a depth we can't finish), the hard limit is checked inside the search
+//! itself and aborts it no matter what. While pondering neither applies, the limits only start
+//! counting at the ponderhit.
+
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use chess::Color;
+
+use super::{SearchControl, SearchLimits};
+
+// Time lost to the GUI and the OS between us printing a move and the clock stopping
+const MOVE_OVERHEAD: Duration = Duration::from_millis(30);
+
+// Without movestogo, assume the game lasts this many more moves
+const DEFAULT_MOVES_TO_GO: u32 = 30;
+
+// How much longer than planned we are allowed to think when an iteration runs long
+const HARD_LIMIT_FACTOR: u32 = 4;
+
+#[derive(Clone, Debug)]
+pub struct TimeManager {
+    start: Instant,
+    soft_limit: Option<Duration>,
+    hard_limit: Option<Duration>,
+    control: Arc<SearchControl>,
+}
+
+impl TimeManager {
+    pub fn new(limits: &SearchLimits, color_to_move: Color, control: Arc<SearchControl>) -> Self {
+        let start = Instant::now();
+
+        if let Some(movetime) = limits.movetime {
+            let movetime = movetime.saturating_sub(MOVE_OVERHEAD);
+            return TimeManager {
+                start,
+                soft_limit: Some(movetime),
+                hard_limit: Some(movetime),
+                control,
+            };
+        }
+
+        let (time_left, increment) = match color_to_move {
+            Color::White => (limits.wtime, limits.winc),
+            Color::Black => (limits.btime, limits.binc),
+        };
+
+        match time_left {
+            Some(time_left) => {
+                let available = time_left.saturating_sub(MOVE_OVERHEAD);
+                let moves_to_go = limits.movestogo.unwrap_or(DEFAULT_MOVES_TO_GO).max(1);
+
+                // Spread the clock over the remaining moves and spend most of the increment
+                let planned = available / moves_to_go + increment * 3 / 4;
+                let hard_limit = (planned * HARD_LIMIT_FACTOR).min(available);
+
+                TimeManager {
+                    start,
+                    soft_limit: Some(planned.min(hard_limit)),
+                    hard_limit: Some(hard_limit),
+                    control,
+                }
+            }
+            None => TimeManager {
+                start,
+                soft_limit: None,
+                hard_limit: None,
+                control,
+            },
+        }
+    }
+
+    /// Whether the search has to stop at some point, which it doesn't without a clock for the
+    /// side to move.
+    pub fn is_limited(&self) -> bool {
+        self.hard_limit.is_some()
+    }
+
+    /// Time since the search started, including any time spent pondering.
+    pub fn elapsed(&self) -> Duration {
+        self.start.elapsed()
+    }
+
+    /// Whether there is still time to start another iteration.
+    pub fn soft_limit_reached(&self) -> bool {
+        self.limit_reached(self.soft_limit)
+    }
+
+    /// Whether the search has to be aborted right now.
+    pub fn hard_limit_reached(&self) -> bool {
+        self.limit_reached(self.hard_limit)
+    }
+
+    fn limit_reached(&self, limit: Option<Duration>) -> bool {
+        match (limit, self.control.clock_start()) {
+            (Some(limit), Some(clock_start)) => clock_start.elapsed() >= limit,
+            _ => false,
+        }
+    }
+}
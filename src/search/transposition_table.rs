@@ -1,28 +1,50 @@
-//! Evict carefully, but using a big table prevents problems. Storing
-//! upperbound and lowerbound and other crap is also important. This should probably include 
-//! refutations in the future and other optimizations.
+//! Fixed size table shared by every search thread without any locking. Entries are packed into
+//! two 64 bit words, the data and the key XORed with the data. A thread that reads while another
+//! one is halfway through writing gets words that don't XOR back to the key, so the entry just
+//! looks missing instead of corrupted. Four entries make up a bucket the size of a cache line, a
+//! position can only be stored in its own bucket. The first three slots of a bucket keep the
+//! deepest entries of the current search, the last one always takes whatever didn't make it into
+//! them. Every search is a new generation, and entries from older ones are the first to go no
+//! matter how deep they are, so nothing ever has to be evicted. Entries record whether their
+//! eval is exact or only an upper or lower bound. Every entry also keeps the best move (or
+//! refutation) found, which is searched first the next time and makes up the PVs.
 
-use log::debug;
-use std::{
-    collections::HashMap,
-    // ops::{Deref, DerefMut},
-};
+use std::fs::File;
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::mem::size_of;
+use std::path::Path;
+use std::sync::atomic::{AtomicU64, Ordering};
 
-use chess::Board;
+use chess::{Board, ChessMove, Piece, ALL_SQUARES};
 
-// TODO: use size in memory instead of absolute size
-const MAX_ENTRIES: usize = 100000000;
+/// Size of the table when nobody asks for a different one
+pub const DEFAULT_SIZE_MB: usize = 64;
 
-#[allow(dead_code)]
-#[derive(Clone, Debug)]
+const ENTRIES_PER_BUCKET: usize = 4;
+// The rest of the bucket is the always-replace slot
+const DEPTH_PREFERRED_SLOTS: usize = 3;
+
+// Saved tables start with the magic, the format version, the age, the number of buckets and the
+// hash of the position that was being analysed, followed by the key and data words of every
+// entry. Everything is little endian.
+const FILE_MAGIC: [u8; 8] = *b"sc0hash\0";
+const FILE_VERSION: u32 = 2;
+
+// hashfull only looks at this many entries at the start of the table, counting all of them
+// would take longer than some searches
+const HASHFULL_SAMPLE: usize = 1000;
+
+#[derive(Clone, Copy, Debug)]
 pub struct TransTableEntry {
     pub flag: Flag,
     pub eval: f32,
+    /// How many plies were left to search below this position
     pub depth: i32,
+    /// The move that raised alpha or caused the cutoff, None if no move ever did
+    pub best_move: Option<ChessMove>,
 }
 
-#[allow(dead_code)]
-#[derive(Clone, PartialEq, Debug)]
+#[derive(Clone, Copy, PartialEq, Debug)]
 pub enum Flag {
     Lowerbound,
     Upperbound,
@@ -30,100 +52,274 @@ pub enum Flag {
     None,
 }
 
-pub struct TransTable {
-    /// Wrapper of transposition table so it associates hash with an entry
-    // Do NOT directly write
-    pub tt: HashMap<u64, TransTableEntry>,
-    age: u64,
+/// One entry, both words are 0 while it's empty
+#[derive(Default)]
+struct Slot {
+    key: AtomicU64,
+    data: AtomicU64,
 }
 
+#[repr(align(64))]
+#[derive(Default)]
+struct Bucket {
+    slots: [Slot; ENTRIES_PER_BUCKET],
+}
 
-#[allow(dead_code)]
-impl TransTable {
-    /// add_entry needs to be by itself because it needs to check if it needs
-    /// to evict and or to insert.
+pub struct TransTable {
+    /// Do NOT directly write, add_entry has to keep the key and data words in sync
+    buckets: Box<[Bucket]>,
+    /// The generation new entries belong to, goes up by one with every search
+    age: AtomicU64,
+}
 
+impl TransTable {
     pub fn new() -> Self {
+        TransTable::with_size_mb(DEFAULT_SIZE_MB)
+    }
+
+    /// As many buckets as fit into `size_mb` megabytes, but at least one.
+    pub fn with_size_mb(size_mb: usize) -> Self {
         // Allocate all the memory at once because it's expensive af to do on the fly
+        let bucket_count = (size_mb * 1024 * 1024 / size_of::<Bucket>()).max(1);
+        let buckets = (0..bucket_count).map(|_| Bucket::default()).collect();
+
         TransTable {
-            tt: HashMap::with_capacity(MAX_ENTRIES),
-            age: 0,
+            buckets,
+            age: AtomicU64::new(0),
         }
     }
 
-    pub fn add_entry(&mut self, board: Board, entry: TransTableEntry) {
-        // This takes care of the size of the table
-        if need_to_evict(&self.tt) {
-            evict(&mut self.tt);
-            // Evicted from trans table
+    /// Empties every entry. Safe to call while searching, but the search then loses everything
+    /// it had learned.
+    pub fn clear(&self) {
+        for slot in self.buckets.iter().flat_map(|bucket| &bucket.slots) {
+            slot.key.store(0, Ordering::Relaxed);
+            slot.data.store(0, Ordering::Relaxed);
         }
+    }
 
-        self.tt.insert(board.get_hash(), entry);
+    /// How full the table is with entries of the current search in permille, like the UCI
+    /// hashfull info.
+    pub fn hashfull(&self) -> u32 {
+        let age = self.age();
+        let sampled = HASHFULL_SAMPLE.min(self.buckets.len() * ENTRIES_PER_BUCKET);
+        let filled = self
+            .buckets
+            .iter()
+            .flat_map(|bucket| &bucket.slots)
+            .take(sampled)
+            .filter(|slot| {
+                let data = slot.data.load(Ordering::Relaxed);
+                !is_empty(data) && unpack_age(data) == age
+            })
+            .count();
+
+        (filled * 1000 / sampled) as u32
     }
-/*
-    pub fn get(self, board: Board) -> Option<TransTableEntry> {
-        match self.tt.get(&board.get_hash()) {
-            Some(entry) => return Some(*entry),
-            None => None,
-        }
+
+    pub fn get(&self, board: &Board) -> Option<TransTableEntry> {
+        let hash = board.get_hash();
+
+        self.bucket(hash).slots.iter().find_map(|slot| {
+            let data = slot.data.load(Ordering::Relaxed);
+            let key = slot.key.load(Ordering::Relaxed);
+            (key ^ data == hash).then(|| unpack(data))
+        })
     }
-*/
-    pub fn increment_age(&mut self) {
-        self.age += 1;
+
+    /// Goes into the depth-preferred slot holding the same position, or else the one most worth
+    /// losing, if it's from an older search or not any deeper. Otherwise into the always-replace
+    /// slot.
+    pub fn add_entry(&self, board: Board, entry: TransTableEntry) {
+        let hash = board.get_hash();
+        let age = self.age();
+        let data = pack(&entry, age);
+        let (depth_preferred, always_replace) =
+            self.bucket(hash).slots.split_at(DEPTH_PREFERRED_SLOTS);
+
+        let same_position = depth_preferred.iter().find(|slot| {
+            slot.key.load(Ordering::Relaxed) ^ slot.data.load(Ordering::Relaxed) == hash
+        });
+        // Old entries first, then the shallowest
+        let candidate = same_position.unwrap_or_else(|| {
+            depth_preferred
+                .iter()
+                .min_by_key(|slot| {
+                    let data = slot.data.load(Ordering::Relaxed);
+                    (unpack_age(data) == age, unpack_depth(data))
+                })
+                .unwrap()
+        });
+        let old = candidate.data.load(Ordering::Relaxed);
+        let slot = if unpack_age(old) != age || entry.depth >= unpack_depth(old) {
+            candidate
+        } else {
+            &always_replace[0]
+        };
+
+        slot.key.store(hash ^ data, Ordering::Relaxed);
+        slot.data.store(data, Ordering::Relaxed);
     }
-}
 
-fn need_to_evict(table: &HashMap<u64, TransTableEntry>) -> bool {
-    // Simple function, exists for readability
-    table.len() > MAX_ENTRIES
-}
+    /// Writes the whole table to `path`, for `board` to pick up where the analysis stopped.
+    pub fn save(&self, path: &Path, board: &Board) -> io::Result<()> {
+        let mut file = BufWriter::new(File::create(path)?);
 
-fn evict(table: &mut HashMap<u64, TransTableEntry>) {
-    // Currently just evicts down to MAX_ENTRIES - 20% with lowest depth if too full.
-    debug!(
-        "Current size of transposition table: {} entries",
-        table.len()
-    );
-    let num_to_evict = table.len() - (MAX_ENTRIES - (MAX_ENTRIES / 5));
-    let mut num_evicted = 0;
-    let mut eviction_list: Vec<u64> = vec![];
-
-    debug!(
-        "Started attempting to evict {} entries from transposition table",
-        num_to_evict
-    );
-
-    debug!("Getting eviction list");
-    while num_evicted < num_to_evict {
-        least_depth_entries(table, &mut eviction_list);
-
-        while !eviction_list.is_empty() && num_evicted < num_to_evict {
-            table.remove(&eviction_list.pop().expect("This should not be possible"));
-            num_evicted += 1;
+        file.write_all(&FILE_MAGIC)?;
+        file.write_all(&FILE_VERSION.to_le_bytes())?;
+        file.write_all(&self.age.load(Ordering::Relaxed).to_le_bytes())?;
+        file.write_all(&(self.buckets.len() as u64).to_le_bytes())?;
+        file.write_all(&board.get_hash().to_le_bytes())?;
+        for slot in self.buckets.iter().flat_map(|bucket| &bucket.slots) {
+            file.write_all(&slot.key.load(Ordering::Relaxed).to_le_bytes())?;
+            file.write_all(&slot.data.load(Ordering::Relaxed).to_le_bytes())?;
         }
+
+        file.flush()
     }
 
-    debug!("Evicted {} entries from transposition tables", num_to_evict);
-}
+    /// Reads back a table written by save, as long as it was saved for the same position as
+    /// `board`. The table comes back at the size it was saved with.
+    pub fn load(path: &Path, board: &Board) -> io::Result<Self> {
+        let mut file = BufReader::new(File::open(path)?);
 
-fn least_depth_entries(table: &HashMap<u64, TransTableEntry>, eviction_list: &mut Vec<u64>) {
-    let mut lowest_depth = i32::MAX;
+        let mut magic = [0; 8];
+        file.read_exact(&mut magic)?;
+        if magic != FILE_MAGIC {
+            return Err(invalid_data("not a saved transposition table"));
+        }
+        let mut version = [0; 4];
+        file.read_exact(&mut version)?;
+        if u32::from_le_bytes(version) != FILE_VERSION {
+            return Err(invalid_data("saved with a different version of the format"));
+        }
+        let age = read_u64(&mut file)?;
+        let bucket_count = read_u64(&mut file)? as usize;
+        if read_u64(&mut file)? != board.get_hash() {
+            return Err(invalid_data("saved while analysing a different position"));
+        }
 
-    // Find the lowest depth in the map
-    for trans_table_entry in table.values() {
-        if trans_table_entry.depth < lowest_depth {
-            lowest_depth = trans_table_entry.depth;
+        // Allocated as it's read, so a file lying about its size runs out before the memory does
+        let mut buckets = Vec::new();
+        for _ in 0..bucket_count {
+            let bucket = Bucket::default();
+            for slot in &bucket.slots {
+                slot.key.store(read_u64(&mut file)?, Ordering::Relaxed);
+                slot.data.store(read_u64(&mut file)?, Ordering::Relaxed);
+            }
+            buckets.push(bucket);
+        }
+        if buckets.is_empty() {
+            return Err(invalid_data("saved without any entries"));
         }
+
+        Ok(TransTable {
+            buckets: buckets.into_boxed_slice(),
+            age: AtomicU64::new(age),
+        })
     }
 
-    debug!("Found lowest depth value of {}", lowest_depth);
+    /// Starts a new generation, everything stored before becomes fair game for replacement.
+    pub fn increment_age(&self) {
+        self.age.fetch_add(1, Ordering::Relaxed);
+    }
 
-    // Returns all of keys of the lowest depth
-    for (board, trans_table_entry) in table {
-        if trans_table_entry.depth == lowest_depth {
-            eviction_list.push(*board);
-        }
+    fn age(&self) -> u64 {
+        self.age.load(Ordering::Relaxed) & AGE_MASK
+    }
+
+    fn bucket(&self, hash: u64) -> &Bucket {
+        // Maps the hash onto the buckets without the bias (or the division) of a modulo
+        let index = (hash as u128 * self.buckets.len() as u128) >> 64;
+        &self.buckets[index as usize]
     }
+}
+
+fn read_u64(file: &mut impl Read) -> io::Result<u64> {
+    let mut bytes = [0; 8];
+    file.read_exact(&mut bytes)?;
+    Ok(u64::from_le_bytes(bytes))
+}
+
+fn invalid_data(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+// Layout of the data word, from the lowest bit up: the eval's 32 bits, 16 for the best move,
+// 7 for the depth, 3 for the flag and the remaining 6 for the age. Every flag is stored as
+// non-zero, an entry that packs to 0 everywhere else is still told apart from an empty slot.
+// Ages wrap around, an entry from 64 searches ago looks current again, but it's long gone by
+// then.
+const MOVE_SHIFT: u32 = 32;
+const DEPTH_SHIFT: u32 = 48;
+const DEPTH_MASK: u64 = 0b1111111;
+const FLAG_SHIFT: u32 = 55;
+const FLAG_MASK: u64 = 0b111;
+const AGE_SHIFT: u32 = 58;
+const AGE_MASK: u64 = 0b111111;
+
+// Set in the 16 move bits whenever there is a best move, from and to squares take 6 bits each
+// and the promotion 3
+const HAS_MOVE: u64 = 1 << 15;
+const PROMOTIONS: [Piece; 4] = [Piece::Knight, Piece::Bishop, Piece::Rook, Piece::Queen];
+
+fn pack(entry: &TransTableEntry, age: u64) -> u64 {
+    let flag: u64 = match entry.flag {
+        Flag::Lowerbound => 1,
+        Flag::Upperbound => 2,
+        Flag::Exact => 3,
+        Flag::None => 4,
+    };
+    let best_move = entry.best_move.map_or(0, |chess_move| {
+        let promotion = chess_move.get_promotion().map_or(0, |piece| {
+            PROMOTIONS.iter().position(|&promotion| promotion == piece).unwrap() as u64 + 1
+        });
+        HAS_MOVE
+            | chess_move.get_source().to_index() as u64
+            | (chess_move.get_dest().to_index() as u64) << 6
+            | promotion << 12
+    });
+
+    entry.eval.to_bits() as u64
+        | best_move << MOVE_SHIFT
+        | (entry.depth.clamp(0, DEPTH_MASK as i32) as u64) << DEPTH_SHIFT
+        | flag << FLAG_SHIFT
+        | age << AGE_SHIFT
+}
+
+fn unpack(data: u64) -> TransTableEntry {
+    let flag = match (data >> FLAG_SHIFT) & FLAG_MASK {
+        1 => Flag::Lowerbound,
+        2 => Flag::Upperbound,
+        3 => Flag::Exact,
+        _ => Flag::None,
+    };
+    let best_move = (data >> MOVE_SHIFT) & 0xffff;
+    let best_move = (best_move & HAS_MOVE != 0).then(|| {
+        let square = |index: u64| ALL_SQUARES[(index & 0b111111) as usize];
+        let promotion = match (best_move >> 12) & 0b111 {
+            0 => None,
+            piece => PROMOTIONS.get(piece as usize - 1).copied(),
+        };
+        ChessMove::new(square(best_move), square(best_move >> 6), promotion)
+    });
+
+    TransTableEntry {
+        flag,
+        eval: f32::from_bits(data as u32),
+        depth: unpack_depth(data),
+        best_move,
+    }
+}
+
+fn unpack_depth(data: u64) -> i32 {
+    ((data >> DEPTH_SHIFT) & DEPTH_MASK) as i32
+}
+
+fn is_empty(data: u64) -> bool {
+    (data >> FLAG_SHIFT) & FLAG_MASK == 0
+}
 
-    debug!("Got {} keys of lowest depth.", eviction_list.len());
+fn unpack_age(data: u64) -> u64 {
+    (data >> AGE_SHIFT) & AGE_MASK
 }
@@ -11,6 +11,7 @@ pub fn flip_color(input_color: Color) -> Color {
     }
 }
 
+#[allow(dead_code)]
 pub fn dump_top_moves(moves: &Vec<ChessMove>) -> Vec<String> {
     let mut output: Vec<String> = vec![];
 
@@ -21,7 +22,8 @@ pub fn dump_top_moves(moves: &Vec<ChessMove>) -> Vec<String> {
     output
 }
 
-pub fn fast_board_status(board: Board) -> BoardStatus {
+#[allow(dead_code)]
+pub fn fast_board_status(_board: Board) -> BoardStatus {
     // If king is checked
     // Can the king move
     // Can pieces block
@@ -37,10 +39,12 @@ pub fn fast_board_status(board: Board) -> BoardStatus {
 
 // Stolen shamelessly from https://github.com/rust-lang/rust/issues/72353 because
 // there is no native atomic f64 support
+#[allow(dead_code)]
 #[derive(Debug)]
 pub struct AtomicF32 {
     storage: AtomicU32,
 }
+#[allow(dead_code)]
 impl AtomicF32 {
     pub fn new(value: f32) -> Self {
         let as_u32 = value.to_bits();
@@ -1,8 +1,27 @@
-#[cfg(test)]
-use crate::search;
-use chess::{Board, Color};
-use std::str::FromStr;
+use crate::search::move_ordering::{self, MovePicker, OrderingTables};
+use crate::search::see;
 use crate::search::transposition_table::{Flag, TransTable, TransTableEntry};
+use crate::search::{
+    self, Algorithm, Score, SearchControl, SearchError, SearchInfo, SearchOptions, SearchResult,
+    CHECKMATE,
+};
+use crate::uci;
+use chess::{Board, ChessMove, Color, MoveGen};
+use std::str::FromStr;
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Searches the way the UCI front-end does for `go <go>`
+fn search_go(
+    board: Board,
+    go: &str,
+    options: &SearchOptions,
+) -> Result<SearchResult, SearchError> {
+    let limits = uci::parse_go(go.split_whitespace());
+    let control = Arc::new(SearchControl::new(limits.ponder));
+    search::search(board, board.side_to_move(), &limits, options, None, control, &mut |_| {})
+}
 
 #[test]
 fn vienna_gambit() {
@@ -52,3 +71,633 @@ fn kasperov4() {
     let best_move = search::iterative_deepening_search(board, color_to_move, 7, None);
     assert!(best_move.to_string() == "f7e7");
 }
+
+#[test]
+fn uci_position() {
+    // Makes sure position commands are parsed the way a GUI sends them
+    let board = uci::parse_position("startpos moves e2e4 e7e5 g1f3".split_whitespace())
+        .expect("Invalid position");
+    assert_eq!(
+        board,
+        Board::from_str("rnbqkbnr/pppp1ppp/8/4p3/4P3/5N2/PPPP1PPP/RNBQKB1R b KQkq - 1 2")
+            .expect("Invalid FEN")
+    );
+
+    let fen = "4k3/2np1p2/4p1Pn/2q5/2P4P/5b2/2r2R2/6K1 b - - 0 34";
+    let board = uci::parse_position(format!("fen {} moves c5f2", fen).split_whitespace())
+        .expect("Invalid position");
+    assert_eq!(board.status(), chess::BoardStatus::Checkmate);
+}
+
+#[test]
+fn movetime() {
+    // Makes sure the search stops on time and still comes back with a legal move
+    let board = Board::from_str("rnbqkb1r/pppp1ppp/5n2/4P3/5p2/2N5/PPPP2PP/R1BQKBNR b KQkq - 0 4")
+        .expect("Invalid FEN");
+    let start = Instant::now();
+    let result = search_go(board, "movetime 200", &SearchOptions::default()).unwrap();
+    assert!(start.elapsed() < Duration::from_millis(1000));
+    assert!(board.legal(result.best_move));
+}
+
+#[test]
+fn opponent_clock() {
+    // Only the clock of the side not to move doesn't limit the search, it still has to end
+    let board = Board::default();
+    let start = Instant::now();
+    let result = search_go(board, "btime 1000 binc 0", &SearchOptions::default()).unwrap();
+    assert!(start.elapsed() < Duration::from_secs(10));
+    assert!(board.legal(result.best_move));
+}
+
+#[test]
+fn stop_infinite() {
+    // Makes sure an infinite search returns a legal move soon after being told to stop
+    let board = Board::default();
+    let limits = uci::parse_go("infinite".split_whitespace());
+    let control = Arc::new(SearchControl::new(false));
+    let search_control = control.clone();
+    let options = SearchOptions::default();
+    let handle = thread::spawn(move || {
+        search::search(board, Color::White, &limits, &options, None, search_control, &mut |_| {})
+    });
+
+    thread::sleep(Duration::from_millis(200));
+    let start = Instant::now();
+    control.stop();
+    let result = handle.join().expect("Search thread panicked").unwrap();
+    assert!(start.elapsed() < Duration::from_millis(500));
+    assert!(board.legal(result.best_move));
+}
+
+#[test]
+fn mate_scores() {
+    // Makes sure mate distances in plies turn into the moves GUIs expect
+    assert_eq!(Score::from_eval(CHECKMATE - 1.0), Score::Mate(1));
+    assert_eq!(Score::from_eval(CHECKMATE - 3.0), Score::Mate(2));
+    assert_eq!(Score::from_eval(-(CHECKMATE - 2.0)), Score::Mate(-1));
+    assert_eq!(Score::from_eval(1.5), Score::Centipawns(150));
+}
+
+#[test]
+fn principal_variation() {
+    // Makes sure the pv is a legal line that actually ends in the mate the search found
+    let mut board = Board::from_str("kbK5/pp6/1P6/8/8/8/8/R7 w - -").expect("Invalid FEN");
+    let result = search_go(board, "depth 4", &SearchOptions::default()).unwrap();
+    assert_eq!(result.pv[0], result.best_move);
+    for chess_move in result.pv {
+        assert!(board.legal(chess_move));
+        board = board.make_move_new(chess_move);
+    }
+    assert_eq!(board.status(), chess::BoardStatus::Checkmate);
+}
+
+#[test]
+fn multipv() {
+    // Makes sure there are as many different lines as asked for and they come out best first
+    let board = Board::from_str("rnbqkb1r/pppp1ppp/5n2/4P3/5p2/2N5/PPPP2PP/R1BQKBNR b KQkq - 0 4")
+        .expect("Invalid FEN");
+    let options = SearchOptions {
+        multipv: 3,
+        ..Default::default()
+    };
+    let result = search_go(board, "depth 3", &options).unwrap();
+    assert_eq!(result.lines.len(), 3);
+    assert_eq!(result.lines[0].chess_move, result.best_move);
+    assert_eq!(result.lines[0].eval, result.eval);
+
+    for (i, line) in result.lines.iter().enumerate() {
+        assert_eq!(line.pv[0], line.chess_move);
+        if i > 0 {
+            assert_ne!(line.chess_move, result.lines[i - 1].chess_move);
+            assert!(line.eval <= result.lines[i - 1].eval);
+        }
+    }
+}
+
+#[test]
+fn searchmoves() {
+    // Makes sure only the given moves are searched, even when missing mate in one
+    let board =
+        Board::from_str("4k3/2np1p2/4p1Pn/2q5/2P4P/5b2/2r2R2/6K1 b - - 0 34").expect("Invalid FEN");
+    let result = search_go(board, "searchmoves e8d8 e8e7 depth 3", &SearchOptions::default());
+    let best_move = result.unwrap().best_move.to_string();
+    assert!(best_move == "e8d8" || best_move == "e8e7");
+
+    let result = search_go(board, "depth 3 searchmoves e2e4", &SearchOptions::default());
+    assert_eq!(result.unwrap_err(), SearchError::NoLegalSearchMoves);
+}
+
+#[test]
+fn ponderhit() {
+    // Makes sure the clock only starts running at the ponderhit
+    let board = Board::default();
+    let limits = uci::parse_go("ponder movetime 200".split_whitespace());
+    let control = Arc::new(SearchControl::new(limits.ponder));
+    let search_control = control.clone();
+    let options = SearchOptions::default();
+    let handle = thread::spawn(move || {
+        search::search(board, Color::White, &limits, &options, None, search_control, &mut |_| {})
+    });
+
+    thread::sleep(Duration::from_millis(400));
+    assert!(!handle.is_finished());
+
+    let start = Instant::now();
+    control.ponderhit();
+    let result = handle.join().expect("Search thread panicked").unwrap();
+    assert!(start.elapsed() < Duration::from_millis(1000));
+    assert!(board.legal(result.best_move));
+}
+
+#[test]
+fn node_limit() {
+    // Makes sure node limited searches stay within the limit and are reproducible
+    let board = Board::from_str("rnbqkb1r/pppp1ppp/5n2/4P3/5p2/2N5/PPPP2PP/R1BQKBNR b KQkq - 0 4")
+        .expect("Invalid FEN");
+    let first = search_go(board, "nodes 20000", &SearchOptions::default()).unwrap();
+    let second = search_go(board, "nodes 20000", &SearchOptions::default()).unwrap();
+    assert!(first.nodes <= 20000);
+    assert_eq!(first.nodes, second.nodes);
+    assert_eq!(first.pv, second.pv);
+}
+
+#[test]
+fn go_mate() {
+    // Makes sure go mate stops once the mate is proven
+    let board = Board::from_str("kbK5/pp6/1P6/8/8/8/8/R7 w - -").expect("Invalid FEN");
+    let result = search_go(board, "mate 2", &SearchOptions::default()).unwrap();
+    assert_eq!(result.best_move.to_string(), "a1a6");
+    assert_eq!(Score::from_eval(result.eval), Score::Mate(2));
+    assert!(result.depth <= 3);
+
+    // Mates too long to ever search for can't overflow the depth they are turned into
+    for go in ["mate 2000000000", "mate 3000000000", "mate 99999999999"] {
+        let result = search_go(board, go, &SearchOptions::default()).unwrap();
+        assert_eq!(result.best_move.to_string(), "a1a6");
+    }
+}
+
+#[test]
+fn quiescence() {
+    // Makes sure a depth 1 search sees the recapture instead of grabbing a defended pawn
+    let board = Board::from_str("4k3/8/3p4/4p3/3Q4/8/8/4K3 w - - 0 1").expect("Invalid FEN");
+    let result = search_go(board, "depth 1", &SearchOptions::default()).unwrap();
+    assert_ne!(result.best_move.to_string(), "d4e5");
+    assert!(result.eval > 5.0);
+}
+
+#[test]
+fn hash_move_pv() {
+    // Makes sure lines cut short by transposition table hits still come out at least as long as
+    // the depth searched. How much longer depends on what the table holds.
+    let mut board = Board::from_str("rnbqkb1r/pppp1ppp/5n2/4P3/5p2/2N5/PPPP2PP/R1BQKBNR b KQkq - 0 4")
+        .expect("Invalid FEN");
+    let result = search_go(board, "depth 6", &SearchOptions::default()).unwrap();
+    assert!(result.pv.len() >= 6);
+    for chess_move in result.pv {
+        assert!(board.legal(chess_move));
+        board = board.make_move_new(chess_move);
+    }
+}
+
+#[test]
+fn algorithms() {
+    // Makes sure every algorithm comes up with the same eval as plain minimax, as long as nothing
+    // gets pruned that minimax would have looked at
+    for fen in [
+        "rnbqkb1r/pppp1ppp/5n2/4P3/5p2/2N5/PPPP2PP/R1BQKBNR b KQkq - 0 4",
+        "4k2r/1R3R2/p3p1pp/4b3/1BnNr3/8/P1P5/5K2 w - - 1 0",
+    ] {
+        let board = Board::from_str(fen).expect("Invalid FEN");
+        let minmax = SearchOptions {
+            algorithm: Algorithm::MinMax,
+            ..Default::default()
+        };
+        let reference = search_go(board, "depth 3", &minmax).unwrap();
+
+        for algorithm in [Algorithm::ABPrune, Algorithm::PVS, Algorithm::MTDF] {
+            let options = SearchOptions {
+                algorithm,
+                late_move_reductions: false,
+                futility_pruning: false,
+                extensions: false,
+                ..Default::default()
+            };
+            let result = search_go(board, "depth 3", &options).unwrap();
+            assert_eq!(result.eval, reference.eval, "{} on {}", algorithm, fen);
+        }
+    }
+
+    assert_eq!("alphabeta".parse::<Algorithm>(), Ok(Algorithm::ABPrune));
+    assert_eq!(
+        search::new_search(
+            Board::from_str("kbK5/pp6/1P6/8/8/8/8/R7 w - -").expect("Invalid FEN"),
+            Color::White,
+            3,
+            Algorithm::MTDF,
+            None
+        )
+        .map(|chess_move| chess_move.to_string()),
+        Some("a1a6".to_string())
+    );
+}
+
+#[test]
+fn zugzwang() {
+    // Makes sure null move pruning doesn't hide a zugzwang, only Kh6 leaves black without a move
+    let board = Board::from_str("1q1k4/2Rr4/8/2Q3K1/8/8/8/8 w - - 0 1").expect("Invalid FEN");
+    let result = search_go(board, "depth 9", &SearchOptions::default()).unwrap();
+    assert_eq!(result.best_move.to_string(), "g5h6");
+}
+
+#[test]
+fn late_move_reductions() {
+    // Makes sure reducing late moves saves nodes without missing the mate
+    let board =
+        Board::from_str("4k2r/1R3R2/p3p1pp/4b3/1BnNr3/8/P1P5/5K2 w - - 1 0").expect("Invalid FEN");
+    let reduced = search_go(board, "depth 7", &SearchOptions::default()).unwrap();
+    let options = SearchOptions {
+        late_move_reductions: false,
+        ..Default::default()
+    };
+    let full = search_go(board, "depth 7", &options).unwrap();
+
+    assert_eq!(reduced.best_move.to_string(), "f7e7");
+    assert_eq!(full.best_move.to_string(), "f7e7");
+    assert!(reduced.nodes < full.nodes);
+}
+
+#[test]
+fn futility_pruning() {
+    // Pruning near the horizon must neither miss the quiet mate after a1a6 bxa6 b7# nor the
+    // zugzwang that makes every other black move lose. The search stops as soon as the mate is
+    // proven, so both searches get just as far before the node counts are compared.
+    let board = Board::from_str("kbK5/pp6/1P6/8/8/8/8/R7 w - - 0 1").expect("Invalid FEN");
+    let pruned = search_go(board, "mate 2", &SearchOptions::default()).unwrap();
+    let options = SearchOptions {
+        futility_pruning: false,
+        ..Default::default()
+    };
+    let full = search_go(board, "mate 2", &options).unwrap();
+
+    assert_eq!(pruned.best_move.to_string(), "a1a6");
+    assert_eq!(full.best_move.to_string(), "a1a6");
+    assert!(pruned.nodes <= full.nodes);
+
+    // Three plies hardly leave anything to prune, it takes a deeper search to see it pay off
+    let board = Board::from_str("rnbqkb1r/pppp1ppp/5n2/4P3/5p2/2N5/PPPP2PP/R1BQKBNR b KQkq - 0 4")
+        .expect("Invalid FEN");
+    let pruned = search_go(board, "depth 5", &SearchOptions::default()).unwrap();
+    let full = search_go(board, "depth 5", &options).unwrap();
+    assert!(pruned.nodes < full.nodes);
+}
+
+#[test]
+fn aspiration_windows() {
+    // Every iteration has to end on an exact eval, no matter how often its window failed
+    let board = Board::from_str("rnbqkb1r/pppp1ppp/5n2/4P3/5p2/2N5/PPPP2PP/R1BQKBNR b KQkq - 0 4")
+        .expect("Invalid FEN");
+    let limits = uci::parse_go("depth 7".split_whitespace());
+    let control = Arc::new(SearchControl::new(false));
+    let mut infos: Vec<SearchInfo> = vec![];
+    let result = search::search(
+        board,
+        Color::Black,
+        &limits,
+        &SearchOptions::default(),
+        None,
+        control,
+        &mut |info| infos.push(info.clone()),
+    )
+    .unwrap();
+
+    for (info, next) in infos.iter().zip(infos.iter().skip(1)) {
+        if info.bound != Flag::Exact {
+            assert_eq!(info.depth, next.depth);
+        }
+    }
+    let last = infos.last().unwrap();
+    assert_eq!(last.depth, 7);
+    assert_eq!(last.bound, Flag::Exact);
+    assert_eq!(last.score, Score::from_eval(result.eval));
+
+    let failed_high = SearchInfo {
+        bound: Flag::Lowerbound,
+        ..last.clone()
+    };
+    assert!(uci::format_info(&failed_high).contains(" lowerbound nodes "));
+}
+
+#[test]
+fn extensions() {
+    // Extending the checks finds the mate two plies earlier than the fixed depth would
+    let board =
+        Board::from_str("4k2r/1R3R2/p3p1pp/4b3/1BnNr3/8/P1P5/5K2 w - - 1 0").expect("Invalid FEN");
+    let extended = search_go(board, "depth 5", &SearchOptions::default()).unwrap();
+    let options = SearchOptions {
+        extensions: false,
+        ..Default::default()
+    };
+    let fixed = search_go(board, "depth 5", &options).unwrap();
+
+    assert_eq!(extended.best_move.to_string(), "f7e7");
+    assert_eq!(Score::from_eval(extended.eval), Score::Mate(4));
+    assert!(matches!(Score::from_eval(fixed.eval), Score::Centipawns(_)));
+}
+
+#[test]
+fn static_exchange() {
+    // Known exchanges, in the engine's own centipawn piece values
+    for (fen, chess_move, expected) in [
+        // Undefended pawn
+        ("1k1r4/1pp4p/p7/4p3/8/P5P1/1PP4P/2K1R3 w - - 0 1", "e1e5", 100),
+        // Knight, rook and queen against knight, bishop and queen, with x-rays on both sides
+        ("1k1r3q/1ppn3p/p4b2/4p3/8/P2N2P1/1PP1R1BP/2K1Q3 w - - 0 1", "d3e5", -200),
+        // Queen for a pawn
+        ("4k3/8/2p5/3p4/8/8/3Q4/4K3 w - - 0 1", "d2d5", -1100),
+        // The king can't take back, the rook behind the queen guards d5
+        ("8/8/4k3/3p4/8/8/3Q4/3R2K1 w - - 0 1", "d2d5", 100),
+        ("4k3/8/8/3pP3/8/8/8/4K3 w - d6 0 1", "e5d6", 100),
+        ("3r3k/4P3/8/8/8/8/8/4K3 w - - 0 1", "e7d8q", 1600),
+        // Promoting right in front of the rook only loses the pawn
+        ("3r3k/4P3/8/8/8/8/8/4K3 w - - 0 1", "e7e8q", -100),
+    ] {
+        let board = Board::from_str(fen).expect("Invalid FEN");
+        let chess_move = ChessMove::from_str(chess_move).expect("Invalid move");
+        assert_eq!(see::see(&board, chess_move), expected, "{} in {}", chess_move, fen);
+    }
+}
+
+/// Every move `picker` hands out, in order
+fn pick_all(mut picker: MovePicker, tables: &OrderingTables) -> Vec<ChessMove> {
+    let mut moves = vec![];
+    while let Some(chess_move) = picker.next(tables) {
+        moves.push(chess_move);
+    }
+    moves
+}
+
+#[test]
+fn move_picker_stages() {
+    // The hash move comes first, then the capture that wins a pawn, the quiet moves and last the
+    // queen taking a defended pawn. Every legal move comes out exactly once.
+    let board = Board::from_str("4k3/8/2p5/3pp3/8/8/3Q4/4RK2 w - - 0 1").expect("Invalid FEN");
+    let tables = OrderingTables::new();
+    let hash_move = ChessMove::from_str("d2d3").expect("Invalid move");
+
+    let moves = pick_all(MovePicker::new(&board, 1, Some(hash_move), None), &tables);
+    assert_eq!(moves[0], hash_move);
+    assert_eq!(moves[1], ChessMove::from_str("e1e5").unwrap());
+    assert_eq!(moves[moves.len() - 1], ChessMove::from_str("d2d5").unwrap());
+    assert!(moves[2..moves.len() - 1].iter().all(|&m| move_ordering::is_quiet(&board, m)));
+
+    let mut legal: Vec<ChessMove> = MoveGen::new_legal(&board).collect();
+    let mut picked = moves.clone();
+    legal.sort();
+    picked.sort();
+    assert_eq!(picked, legal);
+
+    // A hash move from a different position that shares the hash isn't legal here
+    let illegal = ChessMove::from_str("a1a8").expect("Invalid move");
+    let moves = pick_all(MovePicker::new(&board, 1, Some(illegal), None), &tables);
+    assert!(!moves.contains(&illegal));
+    assert_eq!(moves.len(), legal.len());
+}
+
+#[test]
+fn killers_and_counter_moves() {
+    // The killer of the ply, then the counter-move to the previous move, come right after the
+    // captures and ahead of every other quiet move
+    let board = Board::from_str("4k3/8/2p5/3pp3/8/8/3Q4/4RK2 w - - 0 1").expect("Invalid FEN");
+    let mut tables = OrderingTables::new();
+    let killer = ChessMove::from_str("d2h6").expect("Invalid move");
+    let counter_move = ChessMove::from_str("f1g1").expect("Invalid move");
+    let previous_move = ChessMove::from_str("e7e5").expect("Invalid move");
+    tables.record_cutoff(&board, 3, 1, killer, None, &[]);
+    tables.record_cutoff(&board, 4, 1, counter_move, Some(previous_move), &[]);
+
+    let moves = pick_all(MovePicker::new(&board, 3, None, Some(previous_move)), &tables);
+    assert_eq!(moves[0], ChessMove::from_str("e1e5").unwrap());
+    assert_eq!(moves[1], killer);
+    assert_eq!(moves[2], counter_move);
+    assert_eq!(moves.iter().filter(|&&m| m == killer || m == counter_move).count(), 2);
+}
+
+#[test]
+fn history_heuristic() {
+    // Quiet moves come out by how many and how deep cutoffs they caused, the ones tried before a
+    // cutoff without causing one go last. Halving everything keeps that order.
+    let board = Board::from_str("4k3/8/2p5/3pp3/8/8/3Q4/4RK2 w - - 0 1").expect("Invalid FEN");
+    let mut tables = OrderingTables::new();
+    let deep = ChessMove::from_str("d2g5").expect("Invalid move");
+    let shallow = ChessMove::from_str("e1e2").expect("Invalid move");
+    let failed = ChessMove::from_str("d2a5").expect("Invalid move");
+    tables.record_cutoff(&board, 10, 3, deep, None, &[]);
+    tables.record_cutoff(&board, 10, 2, shallow, None, &[failed]);
+
+    // No killers at this ply and no previous move, so quiets are only ordered by history
+    let quiets = |tables: &OrderingTables| -> Vec<ChessMove> {
+        pick_all(MovePicker::new(&board, 20, None, None), tables)
+            .into_iter()
+            .filter(|&m| move_ordering::is_quiet(&board, m))
+            .collect()
+    };
+    let moves = quiets(&tables);
+    assert_eq!(moves[..2], [deep, shallow]);
+    assert_eq!(moves[moves.len() - 1], failed);
+
+    // Big enough to pass the maximum on its own, which halves every score
+    tables.record_cutoff(&board, 10, 400, shallow, None, &[]);
+    let moves = quiets(&tables);
+    assert_eq!(moves[..2], [shallow, deep]);
+    assert_eq!(moves[moves.len() - 1], failed);
+}
+
+#[test]
+fn transposition_table() {
+    // Entries have to come back out the way they went in, from any number of threads at once
+    let tt = Arc::new(TransTable::new());
+    let board = Board::from_str("3r3k/4P3/8/8/8/8/8/4K3 w - - 0 1").expect("Invalid FEN");
+    let promotion = ChessMove::from_str("e7d8q").expect("Invalid move");
+    tt.add_entry(
+        board,
+        TransTableEntry {
+            flag: Flag::Lowerbound,
+            eval: 15.5,
+            depth: 7,
+            best_move: Some(promotion),
+        },
+    );
+    let entry = tt.get(&board).unwrap();
+    assert_eq!(entry.flag, Flag::Lowerbound);
+    assert_eq!(entry.eval, 15.5);
+    assert_eq!(entry.depth, 7);
+    assert_eq!(entry.best_move, Some(promotion));
+
+    let handles: Vec<_> = (0..4)
+        .map(|i| {
+            let tt = tt.clone();
+            thread::spawn(move || {
+                for chess_move in MoveGen::new_legal(&Board::default()).skip(i * 5).take(5) {
+                    let board = Board::default().make_move_new(chess_move);
+                    let entry = TransTableEntry {
+                        flag: Flag::Exact,
+                        eval: -0.25,
+                        depth: i as i32,
+                        best_move: None,
+                    };
+                    tt.add_entry(board, entry);
+                }
+            })
+        })
+        .collect();
+    for handle in handles {
+        handle.join().unwrap();
+    }
+
+    for (i, chess_move) in MoveGen::new_legal(&Board::default()).enumerate() {
+        let entry = tt.get(&Board::default().make_move_new(chess_move)).unwrap();
+        assert_eq!(entry.depth, i as i32 / 5);
+        assert_eq!(entry.best_move, None);
+    }
+    assert!(tt.get(&Board::default()).is_none());
+}
+
+#[test]
+fn hash_size() {
+    // A small table fills up during a search and is empty again once cleared
+    let tt = Arc::new(TransTable::with_size_mb(1));
+    assert_eq!(tt.hashfull(), 0);
+
+    let board = Board::default();
+    let limits = uci::parse_go("depth 6".split_whitespace());
+    let mut hashfull = 0;
+    let result = search::search(
+        board,
+        Color::White,
+        &limits,
+        &SearchOptions::default(),
+        Some(tt.clone()),
+        Arc::new(SearchControl::new(false)),
+        &mut |info| hashfull = info.hashfull,
+    )
+    .unwrap();
+    assert!(hashfull > 0 && hashfull <= 1000);
+    assert_eq!(hashfull, tt.hashfull());
+    // The root itself never gets stored, only the positions below it
+    let after_best_move = board.make_move_new(result.best_move);
+    assert!(tt.get(&after_best_move).is_some());
+
+    tt.clear();
+    assert_eq!(tt.hashfull(), 0);
+    assert!(tt.get(&after_best_move).is_none());
+
+    // Before any search, a lowerbound of 0 without a move stored at depth 0 has every other bit
+    // of its entry at 0, it still counts
+    let tt = TransTable::with_size_mb(0);
+    let entry = TransTableEntry {
+        flag: Flag::Lowerbound,
+        eval: 0.0,
+        depth: 0,
+        best_move: None,
+    };
+    tt.add_entry(board, entry);
+    assert!(tt.get(&board).is_some_and(|entry| entry.flag == Flag::Lowerbound));
+    assert_eq!(tt.hashfull(), 250);
+}
+
+#[test]
+fn replacement() {
+    // A table of a single bucket: three slots for the deepest entries and one for the rest
+    let tt = TransTable::with_size_mb(0);
+    let boards: Vec<Board> = MoveGen::new_legal(&Board::default())
+        .take(6)
+        .map(|chess_move| Board::default().make_move_new(chess_move))
+        .collect();
+    let entry = |depth| TransTableEntry {
+        flag: Flag::Exact,
+        eval: 0.5,
+        depth,
+        best_move: None,
+    };
+
+    tt.increment_age();
+    for board in &boards[..3] {
+        tt.add_entry(*board, entry(10));
+    }
+    tt.add_entry(boards[3], entry(1));
+    tt.add_entry(boards[4], entry(1));
+    assert!(boards[..3].iter().all(|board| tt.get(board).is_some()));
+    assert!(tt.get(&boards[3]).is_none());
+    assert!(tt.get(&boards[4]).is_some());
+    assert_eq!(tt.hashfull(), 1000);
+
+    // Deep entries from the last search make way for shallow ones from this one
+    tt.increment_age();
+    tt.add_entry(boards[5], entry(1));
+    assert!(tt.get(&boards[5]).is_some());
+    assert_eq!(boards[..3].iter().filter(|board| tt.get(board).is_some()).count(), 2);
+    assert_eq!(tt.hashfull(), 250);
+}
+
+#[test]
+fn persist_hash() {
+    // A saved table comes back with its entries, but only for the position it was saved for
+    let board = Board::from_str("kbK5/pp6/1P6/8/8/8/8/R7 w - - 0 1").expect("Invalid FEN");
+    let tt = Arc::new(TransTable::with_size_mb(1));
+    let limits = uci::parse_go("depth 5".split_whitespace());
+    let options = SearchOptions::default();
+    let control = Arc::new(SearchControl::new(false));
+    let tt_raw = Some(tt.clone());
+    let result =
+        search::search(board, Color::White, &limits, &options, tt_raw, control, &mut |_| {})
+            .unwrap();
+
+    let path = std::env::temp_dir().join(format!("sc0-persist-{}.hash", std::process::id()));
+    tt.save(&path, &board).unwrap();
+    let loaded = TransTable::load(&path, &board).unwrap();
+    let after_best_move = board.make_move_new(result.best_move);
+    let saved = tt.get(&after_best_move).unwrap();
+    let reloaded = loaded.get(&after_best_move).unwrap();
+    assert_eq!(reloaded.eval, saved.eval);
+    assert_eq!(reloaded.depth, saved.depth);
+    assert_eq!(reloaded.best_move, saved.best_move);
+    assert_eq!(loaded.hashfull(), tt.hashfull());
+
+    let error = TransTable::load(&path, &Board::default()).err().unwrap();
+    assert_eq!(error.kind(), std::io::ErrorKind::InvalidData);
+
+    std::fs::write(&path, b"not a table").unwrap();
+    assert!(TransTable::load(&path, &board).is_err());
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn mates_in_tt() {
+    // Mates get cached, and come back out counted from wherever the position comes up again
+    let board =
+        Board::from_str("4k2r/1R3R2/p3p1pp/4b3/1BnNr3/8/P1P5/5K2 w - - 1 0").expect("Invalid FEN");
+    let tt = Arc::new(TransTable::new());
+    let search_with_tt = |board: Board, go: &str| {
+        let limits = uci::parse_go(go.split_whitespace());
+        let control = Arc::new(SearchControl::new(false));
+        let options = SearchOptions::default();
+        let side = board.side_to_move();
+        search::search(board, side, &limits, &options, Some(tt.clone()), control, &mut |_| {})
+            .unwrap()
+    };
+
+    let first = search_with_tt(board, "depth 7");
+    assert_eq!(first.best_move.to_string(), "f7e7");
+    assert_eq!(Score::from_eval(first.eval), Score::Mate(4));
+    let after_best_move = board.make_move_new(first.best_move);
+    assert!(tt.get(&after_best_move).is_some_and(|entry| entry.eval < -9000.0));
+
+    let defending = search_with_tt(after_best_move, "depth 6");
+    assert_eq!(Score::from_eval(defending.eval), Score::Mate(-3));
+    // The second time around the mate is already in the table
+    let again = search_with_tt(board, "depth 7");
+    assert_eq!(Score::from_eval(again.eval), Score::Mate(4));
+    assert!(again.nodes < first.nodes);
+}
@@ -0,0 +1,440 @@
+//! UCI front-end. Reads commands from stdin line by line, keeps track of the current
+//! position and drives the search when the GUI sends `go`. Only the bits of the protocol
+//! that matter for playing games are handled, everything else is ignored like the spec asks.
+//! The search runs on its own thread so `stop` and `quit` can be read while it's thinking.
+
+use chess::{Board, ChessMove};
+use log::debug;
+use std::path::Path;
+use std::str::FromStr;
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+use crate::search::transposition_table::{Flag, TransTable, DEFAULT_SIZE_MB};
+use crate::search::{
+    self, Algorithm, Score, SearchControl, SearchInfo, SearchLimits, SearchOptions,
+};
+use crate::{read_commands, ENGINE_NAME};
+
+const ENGINE_AUTHOR: &str = "otisdog8";
+
+const MAX_MULTIPV: usize = 256;
+
+// In megabytes, the table is allocated all at once so there's no point in allowing more than
+// a machine could have
+const MAX_HASH_MB: usize = 65536;
+
+// bench searches every one of these to the same depth, so the algorithms can be compared
+const BENCH_POSITIONS: [&str; 5] = [
+    "rnbqkb1r/pppp1ppp/5n2/4P3/5p2/2N5/PPPP2PP/R1BQKBNR b KQkq - 0 4",
+    "4k3/2np1p2/4p1Pn/2q5/2P4P/5b2/2r2R2/6K1 b - - 0 34",
+    "2R2rk1/4pppp/8/8/8/8/6K1/2R5 w - - 0 1",
+    "kbK5/pp6/1P6/8/8/8/8/R7 w - - 0 1",
+    "4k2r/1R3R2/p3p1pp/4b3/1BnNr3/8/P1P5/5K2 w - - 1 0",
+];
+const BENCH_DEPTH: i32 = 4;
+
+// How often a finished infinite or ponder search checks whether it may send its move yet
+const INFINITE_POLL_INTERVAL: Duration = Duration::from_millis(5);
+
+pub struct Uci {
+    board: Board,
+    options: SearchOptions,
+    tt: Arc<TransTable>,
+    /// Where Save Hash and Load Hash write and read the table
+    hash_file: String,
+    control: Arc<SearchControl>,
+    /// Whether the running search was started with go infinite
+    infinite: bool,
+    search_thread: Option<JoinHandle<()>>,
+}
+
+impl Uci {
+    pub fn new() -> Self {
+        Uci {
+            board: Board::default(),
+            options: SearchOptions::default(),
+            tt: Arc::new(TransTable::new()),
+            hash_file: String::new(),
+            control: Arc::new(SearchControl::new(false)),
+            infinite: false,
+            search_thread: None,
+        }
+    }
+
+    /// Runs until stdin is closed or `quit` is received.
+    pub fn run(&mut self) {
+        read_commands(|line| self.handle_command(line));
+
+        // Let a search started right before stdin closed finish and print its move, unless it
+        // would never finish on its own
+        if self.infinite || self.control.is_pondering() {
+            self.stop_search();
+        } else {
+            self.wait_for_search();
+        }
+    }
+
+    /// Returns false once the engine should shut down.
+    pub fn handle_command(&mut self, line: &str) -> bool {
+        debug!("UCI command: {}", line);
+        let mut tokens = line.split_whitespace();
+
+        match tokens.next() {
+            Some("uci") => {
+                println!("id name {}", ENGINE_NAME);
+                println!("id author {}", ENGINE_AUTHOR);
+                println!(
+                    "option name MultiPV type spin default 1 min 1 max {}",
+                    MAX_MULTIPV
+                );
+                println!(
+                    "option name Hash type spin default {} min 1 max {}",
+                    DEFAULT_SIZE_MB, MAX_HASH_MB
+                );
+                println!("option name Clear Hash type button");
+                println!("option name Hash File type string default <empty>");
+                println!("option name Save Hash type button");
+                println!("option name Load Hash type button");
+                println!("option name Ponder type check default false");
+                println!("option name LateMoveReductions type check default true");
+                println!("option name FutilityPruning type check default true");
+                println!("option name Extensions type check default true");
+                let algorithms: Vec<String> = Algorithm::ALL
+                    .iter()
+                    .map(|algorithm| format!("var {}", algorithm))
+                    .collect();
+                println!(
+                    "option name Algorithm type combo default {} {}",
+                    Algorithm::default(),
+                    algorithms.join(" ")
+                );
+                println!("uciok");
+            }
+            Some("isready") => println!("readyok"),
+            Some("setoption") => self.set_option(tokens),
+            Some("ucinewgame") => {
+                self.stop_search();
+                self.board = Board::default();
+                self.tt.clear();
+            }
+            Some("position") => match parse_position(tokens) {
+                Some(board) => self.board = board,
+                None => debug!("Ignoring invalid position command: {}", line),
+            },
+            Some("go") => self.go(tokens),
+            Some("ponderhit") => self.control.ponderhit(),
+            Some("stop") => self.stop_search(),
+            Some("bench") => self.bench(tokens),
+            Some("quit") => {
+                self.stop_search();
+                return false;
+            }
+            Some(command) => debug!("Unknown command: {}", command),
+            None => {}
+        }
+
+        true
+    }
+
+    fn go<'a>(&mut self, tokens: impl Iterator<Item = &'a str>) {
+        self.stop_search();
+
+        let limits = parse_go(tokens);
+
+        let board = self.board;
+        let options = self.options.clone();
+        let tt = self.tt.clone();
+        let control = Arc::new(SearchControl::new(limits.ponder));
+        self.control = control.clone();
+        self.infinite = limits.infinite;
+
+        self.search_thread = Some(thread::spawn(move || {
+            let result = search::search(
+                board,
+                board.side_to_move(),
+                &limits,
+                &options,
+                Some(tt),
+                control.clone(),
+                &mut |info| println!("{}", format_info(info)),
+            );
+
+            // Infinite and ponder searches can't send bestmove before the GUI asks for it
+            while (limits.infinite || control.is_pondering()) && !control.is_stopped() {
+                thread::sleep(INFINITE_POLL_INTERVAL);
+            }
+
+            match result {
+                // The move we expect in reply is what the GUI should let us ponder on
+                Ok(result) => match result.pv.get(1) {
+                    Some(ponder_move) => {
+                        println!("bestmove {} ponder {}", result.best_move, ponder_move)
+                    }
+                    None => println!("bestmove {}", result.best_move),
+                },
+                // The spec wants a null move when there is nothing to play
+                Err(error) => {
+                    println!("info string {}", error);
+                    println!("bestmove 0000");
+                }
+            }
+        }));
+    }
+
+    /// Handles `setoption name <name> [value <value>]`. Option names aren't case sensitive.
+    fn set_option<'a>(&mut self, tokens: impl Iterator<Item = &'a str>) {
+        let (name, value) = parse_option(tokens);
+
+        match name.to_lowercase().as_str() {
+            "multipv" => match value.parse::<usize>() {
+                Ok(multipv) => self.options.multipv = multipv.clamp(1, MAX_MULTIPV),
+                Err(_) => debug!("Invalid MultiPV value: {}", value),
+            },
+            "hash" => match value.parse::<usize>() {
+                Ok(size_mb) => {
+                    self.stop_search();
+                    self.tt = Arc::new(TransTable::with_size_mb(size_mb.clamp(1, MAX_HASH_MB)));
+                }
+                Err(_) => debug!("Invalid Hash value: {}", value),
+            },
+            "clear hash" => self.tt.clear(),
+            "hash file" => self.hash_file = value,
+            // The table is saved for the current position and can only be loaded for it again
+            "save hash" => {
+                self.stop_search();
+                match self.tt.save(Path::new(&self.hash_file), &self.board) {
+                    Ok(()) => println!("info string saved hash to {}", self.hash_file),
+                    Err(error) => println!("info string couldn't save hash: {}", error),
+                }
+            }
+            "load hash" => {
+                self.stop_search();
+                match TransTable::load(Path::new(&self.hash_file), &self.board) {
+                    Ok(tt) => {
+                        self.tt = Arc::new(tt);
+                        println!("info string loaded hash from {}", self.hash_file);
+                    }
+                    Err(error) => println!("info string couldn't load hash: {}", error),
+                }
+            }
+            // Pondering is up to the GUI, it just has to know we can do it
+            "ponder" => {}
+            "latemovereductions" => match value.parse::<bool>() {
+                Ok(enabled) => self.options.late_move_reductions = enabled,
+                Err(_) => debug!("Invalid LateMoveReductions value: {}", value),
+            },
+            "futilitypruning" => match value.parse::<bool>() {
+                Ok(enabled) => self.options.futility_pruning = enabled,
+                Err(_) => debug!("Invalid FutilityPruning value: {}", value),
+            },
+            "extensions" => match value.parse::<bool>() {
+                Ok(enabled) => self.options.extensions = enabled,
+                Err(_) => debug!("Invalid Extensions value: {}", value),
+            },
+            "algorithm" => match value.parse::<Algorithm>() {
+                Ok(algorithm) => self.options.algorithm = algorithm,
+                Err(error) => debug!("{}", error),
+            },
+            _ => debug!("Unknown option: {}", name),
+        }
+    }
+
+    /// Handles `bench [depth]`, which isn't part of UCI. Searches the bench positions with the
+    /// current options and reports how many nodes that took and how fast it went.
+    fn bench<'a>(&mut self, mut tokens: impl Iterator<Item = &'a str>) {
+        self.stop_search();
+
+        let depth = tokens
+            .next()
+            .and_then(|depth| depth.parse::<i32>().ok())
+            .unwrap_or(BENCH_DEPTH);
+        let limits = SearchLimits {
+            depth: Some(depth),
+            ..Default::default()
+        };
+
+        let start = Instant::now();
+        let mut nodes = 0;
+        for fen in BENCH_POSITIONS {
+            let board = Board::from_str(fen).expect("Invalid bench position");
+            // Every position gets a fresh transposition table, so runs don't depend on each other
+            let result = search::search(
+                board,
+                board.side_to_move(),
+                &limits,
+                &self.options,
+                None,
+                Arc::new(SearchControl::new(false)),
+                &mut |_| {},
+            );
+
+            if let Ok(result) = result {
+                println!(
+                    "info string {} bestmove {} score {} nodes {}",
+                    fen,
+                    result.best_move,
+                    format_score(Score::from_eval(result.eval)),
+                    result.nodes
+                );
+                nodes += result.nodes;
+            }
+        }
+
+        let millis = start.elapsed().as_millis().max(1) as u64;
+        println!(
+            "info string bench {} depth {} nodes {} time {} nps {}",
+            self.options.algorithm,
+            depth,
+            nodes,
+            millis,
+            nodes * 1000 / millis
+        );
+    }
+
+    /// Interrupts the running search, if there is one, and waits until it printed its move.
+    fn stop_search(&mut self) {
+        self.control.stop();
+        self.wait_for_search();
+    }
+
+    fn wait_for_search(&mut self) {
+        if let Some(handle) = self.search_thread.take() {
+            handle.join().expect("Search thread panicked");
+        }
+    }
+}
+
+fn format_score(score: Score) -> String {
+    match score {
+        Score::Centipawns(centipawns) => format!("cp {}", centipawns),
+        Score::Mate(moves) => format!("mate {}", moves),
+    }
+}
+
+pub fn format_info(info: &SearchInfo) -> String {
+    let score = match info.bound {
+        Flag::Lowerbound => format!("{} lowerbound", format_score(info.score)),
+        Flag::Upperbound => format!("{} upperbound", format_score(info.score)),
+        _ => format_score(info.score),
+    };
+    let pv: Vec<String> = info
+        .pv
+        .iter()
+        .map(|chess_move| chess_move.to_string())
+        .collect();
+
+    format!(
+        "info depth {} seldepth {} multipv {} score {} nodes {} nps {} hashfull {} time {} pv {}",
+        info.depth,
+        info.seldepth,
+        info.multipv,
+        score,
+        info.nodes,
+        info.nps(),
+        info.hashfull,
+        info.time.as_millis(),
+        pv.join(" ")
+    )
+}
+
+/// Splits the arguments of `setoption` into name and value, both of which can contain spaces.
+fn parse_option<'a>(tokens: impl Iterator<Item = &'a str>) -> (String, String) {
+    let mut name: Vec<&str> = vec![];
+    let mut value: Vec<&str> = vec![];
+    let mut in_value = false;
+
+    for token in tokens {
+        match token {
+            "name" if !in_value && name.is_empty() => {}
+            "value" if !in_value => in_value = true,
+            _ if in_value => value.push(token),
+            _ => name.push(token),
+        }
+    }
+
+    (name.join(" "), value.join(" "))
+}
+
+/// Parses the arguments of `go`. Unknown or malformed arguments are skipped.
+pub fn parse_go<'a>(tokens: impl Iterator<Item = &'a str>) -> SearchLimits {
+    let mut limits = SearchLimits::default();
+
+    let mut tokens = tokens.peekable();
+
+    while let Some(token) = tokens.next() {
+        // Everything after searchmoves that looks like a move belongs to it
+        if token == "searchmoves" {
+            while let Some(chess_move) = tokens
+                .peek()
+                .and_then(|value| ChessMove::from_str(value).ok())
+            {
+                limits.searchmoves.push(chess_move);
+                tokens.next();
+            }
+            continue;
+        }
+
+        let mut next_number = || tokens.next().and_then(|value| value.parse::<i64>().ok());
+        // Clocks can go negative when the GUI is late, which just means no time left
+        let mut next_millis =
+            || next_number().map(|millis| Duration::from_millis(millis.max(0) as u64));
+
+        match token {
+            "infinite" => limits.infinite = true,
+            "ponder" => limits.ponder = true,
+            "depth" => limits.depth = next_number().map(|depth| depth.max(1) as i32),
+            "nodes" => limits.nodes = next_number().map(|nodes| nodes.max(1) as u64),
+            "mate" => {
+                limits.mate = next_number().map(|moves| moves.clamp(1, u32::MAX as i64) as u32)
+            }
+            "movetime" => limits.movetime = next_millis(),
+            "wtime" => limits.wtime = next_millis(),
+            "btime" => limits.btime = next_millis(),
+            "winc" => limits.winc = next_millis().unwrap_or_default(),
+            "binc" => limits.binc = next_millis().unwrap_or_default(),
+            "movestogo" => limits.movestogo = next_number().map(|moves| moves.max(1) as u32),
+            _ => debug!("Ignoring go argument: {}", token),
+        }
+    }
+
+    limits
+}
+
+/// Parses the arguments of `position startpos|fen <fen> [moves ...]`. Moves are applied until
+/// the first illegal one, which is logged and dropped along with everything after it.
+pub fn parse_position<'a>(mut tokens: impl Iterator<Item = &'a str>) -> Option<Board> {
+    let mut board = match tokens.next()? {
+        "startpos" => Board::default(),
+        "fen" => {
+            let fen: Vec<&str> = tokens
+                .by_ref()
+                .take_while(|token| *token != "moves")
+                .collect();
+            let board = Board::from_str(&fen.join(" ")).ok()?;
+            return Some(apply_moves(board, tokens));
+        }
+        _ => return None,
+    };
+
+    if tokens.next() == Some("moves") {
+        board = apply_moves(board, tokens);
+    }
+
+    Some(board)
+}
+
+fn apply_moves<'a>(mut board: Board, tokens: impl Iterator<Item = &'a str>) -> Board {
+    for token in tokens {
+        match ChessMove::from_str(token) {
+            Ok(chess_move) if board.legal(chess_move) => board = board.make_move_new(chess_move),
+            _ => {
+                debug!("Illegal move in position command: {}", token);
+                break;
+            }
+        }
+    }
+
+    board
+}
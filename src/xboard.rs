@@ -0,0 +1,232 @@
+//! XBoard/CECP front-end for the tools that don't speak UCI. Unlike UCI the engine keeps
+//! track of which side it plays and moves on its own once it's its turn, unless it has been
+//! put in force mode.
+
+use chess::{Board, BoardStatus, ChessMove, Color};
+use log::debug;
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::search::transposition_table::TransTable;
+use crate::search::{self, SearchControl, SearchLimits, SearchOptions};
+use crate::{read_commands, ENGINE_NAME};
+
+pub struct XBoard {
+    board: Board,
+    tt: Arc<TransTable>,
+    /// None means force mode, the engine only keeps track of the moves
+    engine_color: Option<Color>,
+    /// Set by sd
+    max_depth: Option<i32>,
+    /// Set by st, overrides the clock
+    move_time: Option<Duration>,
+    /// Set by level, 0 means the whole game has to be played in one session
+    moves_per_session: u32,
+    increment: Duration,
+    /// Our clock as last reported by the time command
+    time_left: Option<Duration>,
+    engine_moves: u32,
+}
+
+impl XBoard {
+    pub fn new() -> Self {
+        XBoard {
+            board: Board::default(),
+            tt: Arc::new(TransTable::new()),
+            engine_color: Some(Color::Black),
+            max_depth: None,
+            move_time: None,
+            moves_per_session: 0,
+            increment: Duration::ZERO,
+            time_left: None,
+            engine_moves: 0,
+        }
+    }
+
+    /// Runs until stdin is closed or `quit` is received.
+    pub fn run(&mut self) {
+        read_commands(|line| self.handle_command(line));
+    }
+
+    /// Returns false once the engine should shut down.
+    pub fn handle_command(&mut self, line: &str) -> bool {
+        debug!("XBoard command: {}", line);
+        let mut tokens = line.split_whitespace();
+
+        match tokens.next() {
+            Some("xboard") => {}
+            Some("protover") => {
+                println!(
+                    "feature myname=\"{}\" usermove=1 setboard=1 ping=1 sigint=0 sigterm=0 colors=0 done=1",
+                    ENGINE_NAME
+                );
+            }
+            // The time control is sent separately and survives new
+            Some("new") => {
+                self.board = Board::default();
+                self.tt.clear();
+                self.engine_color = Some(Color::Black);
+                self.max_depth = None;
+                self.engine_moves = 0;
+            }
+            Some("force") | Some("result") => self.engine_color = None,
+            Some("go") => {
+                self.engine_color = Some(self.board.side_to_move());
+                self.think();
+            }
+            Some("usermove") => match tokens.next() {
+                Some(token) => self.user_move(token),
+                None => println!("Error (no move given): usermove"),
+            },
+            Some("setboard") => {
+                let fen: Vec<&str> = tokens.collect();
+                match Board::from_str(&fen.join(" ")) {
+                    Ok(board) => self.board = board,
+                    Err(_) => println!("tellusererror Illegal position"),
+                }
+            }
+            Some("sd") => match tokens.next().and_then(|depth| depth.parse().ok()) {
+                Some(depth) => self.max_depth = Some(i32::max(depth, 1)),
+                None => println!("Error (bad depth): {}", line),
+            },
+            Some("st") => match tokens.next().and_then(|seconds| seconds.parse().ok()) {
+                Some(seconds) => {
+                    self.move_time = Some(Duration::from_secs_f64(f64::max(seconds, 0.0)));
+                }
+                None => println!("Error (bad time): {}", line),
+            },
+            Some("level") => match parse_level(tokens) {
+                Some((moves_per_session, increment)) => {
+                    self.moves_per_session = moves_per_session;
+                    self.increment = increment;
+                    self.move_time = None;
+                }
+                None => println!("Error (bad time control): {}", line),
+            },
+            // Clocks are in centiseconds
+            Some("time") => match tokens.next().and_then(|time| time.parse::<i64>().ok()) {
+                Some(time) => self.time_left = Some(Duration::from_millis(time.max(0) as u64 * 10)),
+                None => println!("Error (bad time): {}", line),
+            },
+            Some("otim") => {}
+            Some("ping") => println!("pong {}", tokens.next().unwrap_or("")),
+            Some("quit") => return false,
+            // Acknowledgements of our features and other things we don't care about
+            Some("accepted") | Some("rejected") | Some("random") | Some("post")
+            | Some("nopost") | Some("hard") | Some("easy") | Some("computer") => {}
+            Some(command) => println!("Error (unknown command): {}", command),
+            None => {}
+        }
+
+        true
+    }
+
+    fn user_move(&mut self, token: &str) {
+        match ChessMove::from_str(token) {
+            Ok(chess_move) if self.board.legal(chess_move) => {
+                self.board = self.board.make_move_new(chess_move);
+            }
+            _ => {
+                println!("Illegal move: {}", token);
+                return;
+            }
+        }
+
+        if !self.report_game_end() && self.engine_color == Some(self.board.side_to_move()) {
+            self.think();
+        }
+    }
+
+    fn think(&mut self) {
+        if self.report_game_end() {
+            return;
+        }
+
+        let engine_move = search::search(
+            self.board,
+            self.board.side_to_move(),
+            &self.limits(),
+            &SearchOptions::default(),
+            Some(self.tt.clone()),
+            Arc::new(SearchControl::new(false)),
+            &mut |_| {},
+        )
+        .expect("The game is over, so there should have been no search")
+        .best_move;
+        self.board = self.board.make_move_new(engine_move);
+        self.engine_moves += 1;
+        println!("move {}", engine_move);
+
+        self.report_game_end();
+    }
+
+    fn limits(&self) -> SearchLimits {
+        let mut limits = SearchLimits {
+            depth: self.max_depth,
+            movetime: self.move_time,
+            ..Default::default()
+        };
+
+        if self.move_time.is_none() {
+            if let Some(time_left) = self.time_left {
+                match self.board.side_to_move() {
+                    Color::White => {
+                        limits.wtime = Some(time_left);
+                        limits.winc = self.increment;
+                    }
+                    Color::Black => {
+                        limits.btime = Some(time_left);
+                        limits.binc = self.increment;
+                    }
+                }
+
+                if self.moves_per_session > 0 {
+                    limits.movestogo =
+                        Some(self.moves_per_session - self.engine_moves % self.moves_per_session);
+                }
+            }
+        }
+
+        limits
+    }
+
+    /// Prints the result if the game is over, since the engine is expected to claim it.
+    fn report_game_end(&self) -> bool {
+        match self.board.status() {
+            BoardStatus::Checkmate => {
+                if self.board.side_to_move() == Color::White {
+                    println!("0-1 {{Black mates}}");
+                } else {
+                    println!("1-0 {{White mates}}");
+                }
+                true
+            }
+            BoardStatus::Stalemate => {
+                println!("1/2-1/2 {{Stalemate}}");
+                true
+            }
+            BoardStatus::Ongoing => false,
+        }
+    }
+}
+
+/// Parses `level MPS BASE INC` into moves per session and increment. The base time is only
+/// checked, the GUI tells us what is left on the clock before every move anyway.
+fn parse_level<'a>(mut tokens: impl Iterator<Item = &'a str>) -> Option<(u32, Duration)> {
+    let moves_per_session = tokens.next()?.parse().ok()?;
+
+    // Base is either minutes or minutes:seconds
+    let mut base = tokens.next()?.split(':');
+    base.next()?.parse::<u32>().ok()?;
+    if let Some(seconds) = base.next() {
+        seconds.parse::<u32>().ok()?;
+    }
+
+    let increment: f64 = tokens.next()?.parse().ok()?;
+
+    Some((
+        moves_per_session,
+        Duration::from_secs_f64(increment.max(0.0)),
+    ))
+}